@@ -2,16 +2,34 @@ use client1_interface::Param;
 use ffi_rpc::{
     abi_stable::prefix_type::PrefixTypeTrait,
     async_ffi, async_trait,
-    ffi_rpc_macro::{plugin_impl_call, plugin_impl_instance, plugin_impl_root, plugin_impl_trait},
+    bytes::Bytes,
+    callback::Callback,
+    context::CallContext,
+    ffi_rpc_macro::{
+        plugin_impl_call, plugin_impl_instance, plugin_impl_on_load, plugin_impl_on_unload,
+        plugin_impl_root, plugin_impl_trait,
+    },
     registry::Registry,
-    rmp_serde, tokio,
+    stream::Streaming,
+    tokio,
 };
 
-#[plugin_impl_instance(|| Api(1))]
+#[plugin_impl_instance(|offset: i32| Api(offset))] // created with the config
 #[plugin_impl_root]
 #[plugin_impl_call(client1_interface::Client1Api1, client1_interface::Client1Api2)]
 struct Api(i32);
 
+#[plugin_impl_on_load]
+fn init(_: &Registry) {
+    println!("client1 loaded");
+}
+
+#[plugin_impl_on_unload]
+fn stop() -> Result<(), String> {
+    println!("client1 unloaded");
+    Ok(())
+}
+
 #[plugin_impl_trait]
 impl client1_interface::Client1Api1 for Api {
     async fn add(&self, _: &Registry, p: Param, offset: i32) -> i32 {
@@ -24,4 +42,43 @@ impl client1_interface::Client1Api2 for Api {
     async fn minus(&self, _: &Registry, a: i32, b: i32) -> i32 {
         a - b
     }
+
+    async fn div(&self, _: &Registry, a: i32, b: i32) -> Result<i32, String> {
+        if b == 0 {
+            return Err("divide by zero".to_string());
+        }
+        Ok(a / b)
+    }
+
+    async fn reverse(&self, _: &Registry, mut data: Bytes) -> Bytes {
+        data.reverse();
+        data
+    }
+
+    async fn count(&self, _: &Registry, n: i32) -> Streaming<i32> {
+        (0..n).collect()
+    }
+
+    async fn sum(&self, _: &Registry, mut xs: Streaming<i32>) -> i32 {
+        let mut ret = 0;
+        while let Some(x) = xs.next().await {
+            ret += x.unwrap();
+        }
+        ret
+    }
+
+    async fn steps(&self, _: &Registry, n: i32, progress: Callback<i32, ()>) -> i32 {
+        for i in 1..=n {
+            // The caller gave up, e.g, timed out.
+            if CallContext::current().cancel.is_cancelled() {
+                return i - 1;
+            }
+            progress.call(i).await.unwrap();
+        }
+        n
+    }
+
+    fn offset(&self, _: &Registry) -> i32 {
+        self.0
+    }
 }
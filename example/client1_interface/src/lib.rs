@@ -1,7 +1,9 @@
 use ffi_rpc::{
     abi_stable, async_trait,
+    bytes::Bytes,
+    callback::Callback,
     ffi_rpc_macro::{plugin_api_struct, plugin_api_trait},
-    rmp_serde,
+    stream::Streaming,
 };
 use serde::{Deserialize, Serialize};
 
@@ -11,7 +13,7 @@ pub struct Param {
     pub b: i32,
 }
 
-#[plugin_api_struct]
+#[plugin_api_struct(Client1Api1, Client1Api2, id = "client1")]
 pub struct Client1;
 
 #[plugin_api_trait(Client1)]
@@ -22,4 +24,10 @@ pub trait Client1Api1 {
 #[plugin_api_trait(Client1)]
 pub trait Client1Api2 {
     async fn minus(a: i32, b: i32) -> i32;
+    async fn div(a: i32, b: i32) -> Result<i32, String>;
+    async fn reverse(data: Bytes) -> Bytes; // moved without the codec
+    async fn count(n: i32) -> Streaming<i32>; // polled by the caller
+    async fn sum(xs: Streaming<i32>) -> i32; // polled by the implementation
+    async fn steps(n: i32, progress: Callback<i32, ()>) -> i32; // invoked by the implementation
+    fn offset() -> i32; // called without the executor
 }
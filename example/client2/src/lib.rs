@@ -2,15 +2,19 @@ use client1_interface::{Client1, Param};
 use ffi_rpc::{
     abi_stable::prefix_type::PrefixTypeTrait,
     async_ffi, async_trait,
-    ffi_rpc_macro::{plugin_impl_call, plugin_impl_instance, plugin_impl_root, plugin_impl_trait},
+    ffi_rpc_macro::{
+        plugin_impl_call, plugin_impl_instance, plugin_impl_requires, plugin_impl_root,
+        plugin_impl_trait,
+    },
     registry::Registry,
-    rmp_serde, tokio,
+    tokio,
 };
 use server_interface::Server;
 
 #[plugin_impl_instance(|| Api{})]
 #[plugin_impl_root]
 #[plugin_impl_call(client2_interface::Client2Api)]
+#[plugin_impl_requires("client1", server_interface::ServerApi)]
 struct Api;
 
 #[plugin_impl_trait]
@@ -20,11 +24,15 @@ impl client2_interface::Client2Api for Api {
             println!("Spawn a tokio task");
         })
         .await;
-        let t = Client1::from(r.get("client1").unwrap())
+        let t = r
+            .get_as::<Client1>("client1")
+            .unwrap()
             .add(r, &Param { a: 7, b: 8 }, &9)
             .await;
-        let m = Server::from(r.get("server").unwrap()).add(r).await;
-        let o = Client1::from(r.get("client1").unwrap())
+        let m = r.find::<Server>().unwrap().add(r).await; // any plugin implementing `ServerApi`
+        let o = r
+            .get_default::<Client1>() // `id = "client1"` in `plugin_api_struct`
+            .unwrap()
             .minus(r, &100, &50)
             .await;
         a + b + t + m + o
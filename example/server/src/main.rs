@@ -1,12 +1,15 @@
-use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::{path::Path, time::Duration};
 
 use client1_interface::{Client1, Param};
 use client2_interface::Client2;
 use ffi_rpc::{
     async_ffi, async_trait,
+    bytes::Bytes,
+    callback::Callback,
+    context::CallContext,
     ffi_rpc_macro::{plugin_impl_call, plugin_impl_instance, plugin_impl_mock, plugin_impl_trait},
+    loader::{library_name, Manifest},
     registry::Registry,
-    rmp_serde,
 };
 
 #[plugin_impl_instance(||Server{})]
@@ -17,30 +20,65 @@ struct Server;
 #[plugin_impl_trait]
 impl server_interface::ServerApi for Server {
     async fn add(&self, _: &ffi_rpc::registry::Registry) -> i32 {
+        // Forwarded by client2.
+        let ctx = CallContext::current();
+        println!("request id from the host: {:?}", ctx.get("request-id"));
         10
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let mut r = Registry::default();
-    Server::register_mock(&mut r, "server");
+    let r = Registry::default();
+    Server::register_mock(&r, "server").unwrap();
 
-    let lib1 = Client1::new(
-        format!("./target/debug/{}client1{}", DLL_PREFIX, DLL_SUFFIX).as_ref(),
-        &mut r,
-        "client1",
-    )
-    .unwrap();
+    // Load the plugins listed in the manifest, client1 is loaded with the config `1`.
+    Manifest::from_file("./example/server/plugins.toml".as_ref())
+        .unwrap()
+        .load(&r)
+        .unwrap();
+    let lib1 = r.get_default::<Client1>().unwrap();
     let ret = lib1.add(&r, &Param { a: 2, b: 3 }, &4).await;
     println!("1+2+3+4 should be: {ret}");
+    let ret = lib1.div(&r, &1, &0).await;
+    println!("1/0 should be an error: {ret:?}");
+    let ret = lib1.reverse(&r, Bytes::from(vec![1, 2, 3])).await;
+    println!("[1, 2, 3] reversed should be: {:?}", &*ret);
+    drop(ret); // the returned buffer keeps client1 loaded, which blocks the reload below
+    let mut stream = lib1.count(&r, &3).await;
+    while let Some(x) = stream.next().await {
+        println!("counting: {}", x.unwrap());
+    }
+    drop(stream); // same as the buffer
+    let ret = lib1.sum(&r, (1..=4).collect()).await;
+    println!("1+2+3+4 streamed should be: {ret}");
+    let progress = Callback::new(|i: i32| async move { println!("step {i}/2") });
+    lib1.steps(&r, &2, progress).await;
+    let ret = Client1::from(r.get("client1").unwrap())
+        .with_timeout(Duration::from_secs(1))
+        .try_add(&r, &Param { a: 2, b: 3 }, &4)
+        .await;
+    println!("1+2+3+4 within 1s should be: {ret:?}");
+    println!("client1 offset should be: {}", lib1.offset(&r));
+    for method in r.methods("client1").unwrap() {
+        println!("client1 implements: {}", method.signature);
+    }
 
-    let lib2 = Client2::new(
-        format!("./target/debug/{}client2{}", DLL_PREFIX, DLL_SUFFIX).as_ref(),
-        &mut r,
-        "client2",
+    // The new library should have a different path.
+    let path = std::env::temp_dir().join(library_name("client1_reload"));
+    std::fs::copy(
+        Path::new("./target/debug").join(library_name("client1")),
+        &path,
     )
     .unwrap();
-    let ret = lib2.add(&r, &1, &2).await;
+    r.reload("client1", &path).await.unwrap();
+    let ret = lib1.add(&r, &Param { a: 2, b: 3 }, &4).await;
+    println!("1+2+3+4 should still be: {ret}");
+
+    let lib2 = r.get_as::<Client2>("client2").unwrap();
+    let ctx = CallContext::default().with_metadata("request-id", "42");
+    let ret = ctx.scope(lib2.add(&r, &1, &2)).await;
     println!("1+2+1+7+8+9+10+100-50 should be: {ret}");
+
+    r.shutdown().unwrap();
 }
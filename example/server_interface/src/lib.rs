@@ -1,10 +1,9 @@
 use ffi_rpc::{
     abi_stable, async_trait,
     ffi_rpc_macro::{self, plugin_api},
-    rmp_serde,
 };
 
-#[plugin_api(Server)]
+#[plugin_api(Server, codec = "json")] // requires the `json` feature
 pub trait ServerApi {
     async fn add() -> i32;
 }
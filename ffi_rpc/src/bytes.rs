@@ -0,0 +1,114 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use abi_stable::std_types::RVec;
+
+use crate::plugin::PluginGuard;
+
+/// The byte buffer passed across the boundary without the codec.
+///
+/// As an argument, it is moved to the plugin, while `&[u8]` is lent to the plugin during the call.
+/// As a return value, it is moved back to the caller.
+///
+/// The buffer returned by a library delays the drain of `Registry::reload` until it is dropped,
+/// so `on_unload` of the replaced library is not called while it is in use.
+/// Call [`Bytes::into_vec`] to detach it.
+#[derive(Default)]
+pub struct Bytes {
+    buf: RVec<u8>,
+    /// The library allocated the buffer.
+    library: Option<PluginGuard>,
+}
+
+impl Bytes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the reply of the library without copying.
+    #[doc(hidden)]
+    pub fn from_library(buf: RVec<u8>, library: PluginGuard) -> Self {
+        Self {
+            buf,
+            library: Some(library),
+        }
+    }
+
+    /// Convert into `Vec<u8>`, which is copied if the buffer is allocated by another library.
+    pub fn into_vec(self) -> Vec<u8> {
+        let Bytes { buf, library } = self;
+        let ret = buf.into_vec();
+        drop(library);
+        ret
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Bytes {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Clone for Bytes {
+    fn clone(&self) -> Self {
+        Self::from(self.as_ref())
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl Eq for Bytes {}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Bytes").field(&self.as_ref()).finish()
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(v: Vec<u8>) -> Self {
+        RVec::from(v).into()
+    }
+}
+
+impl From<RVec<u8>> for Bytes {
+    fn from(buf: RVec<u8>) -> Self {
+        Self { buf, library: None }
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(v: &[u8]) -> Self {
+        RVec::from_slice(v).into()
+    }
+}
+
+/// The buffer is copied if it is allocated by another library, which may be replaced by `Registry::reload`.
+impl From<Bytes> for RVec<u8> {
+    fn from(v: Bytes) -> Self {
+        match v.library {
+            Some(_) => RVec::from_slice(&v.buf),
+            None => v.buf,
+        }
+    }
+}
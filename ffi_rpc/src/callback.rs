@@ -0,0 +1,180 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use abi_stable::{
+    std_types::{RResult, RVec},
+    StableAbi,
+};
+use async_ffi::{FfiFuture, FutureExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::Codec,
+    error::{catch_panic, ErrorKind, Reply, RpcError},
+    Error,
+};
+
+/// The FFI-safe callback taking and returning the encoded values, which is invoked by the callee.
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct FfiCallback {
+    data: *const (),
+    call: unsafe extern "C" fn(*const (), RVec<u8>) -> FfiFuture<Reply>,
+    drop: unsafe extern "C" fn(*const ()),
+}
+
+// SAFETY: only created from `Callback`, which is `Send` and `Sync`.
+unsafe impl Send for FfiCallback {}
+unsafe impl Sync for FfiCallback {}
+
+impl FfiCallback {
+    /// Decode the arguments and encode the return value by the codec,
+    /// the panics are caught and replied as `ErrorKind::Panic`.
+    pub fn new<A, R>(callback: Callback<A, R>, codec: Codec) -> Self
+    where
+        A: Serialize + DeserializeOwned + Send + 'static,
+        R: Serialize + DeserializeOwned + Send + 'static,
+    {
+        struct State<A, R> {
+            callback: Callback<A, R>,
+            codec: Codec,
+        }
+
+        unsafe extern "C" fn call<A, R>(data: *const (), args: RVec<u8>) -> FfiFuture<Reply>
+        where
+            A: Serialize + DeserializeOwned + Send + 'static,
+            R: Serialize + DeserializeOwned + Send + 'static,
+        {
+            // SAFETY: `data` is the `Arc<State<A, R>>` created in `FfiCallback::new`.
+            let state = unsafe {
+                Arc::increment_strong_count(data as *const State<A, R>);
+                Arc::from_raw(data as *const State<A, R>)
+            };
+            catch_panic(async move {
+                let args = match state.codec.decode(&args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return RResult::RErr(RpcError::new(ErrorKind::InvalidParams, e));
+                    }
+                };
+                match state.callback.call(args).await {
+                    Ok(ret) => match state.codec.encode(&ret) {
+                        Ok(v) => RResult::ROk(v.into()),
+                        Err(e) => RResult::RErr(RpcError::new(ErrorKind::Failed, e)),
+                    },
+                    Err(e) => RResult::RErr(RpcError::new(ErrorKind::Failed, e.to_string())),
+                }
+            })
+            .into_ffi()
+        }
+
+        unsafe extern "C" fn drop<A, R>(data: *const ()) {
+            // SAFETY: `data` is the `Arc<State<A, R>>` created in `FfiCallback::new`.
+            let state = unsafe { Arc::from_raw(data as *const State<A, R>) };
+            // Never unwind across the boundary.
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                std::mem::drop(state)
+            }));
+        }
+
+        Self {
+            data: Arc::into_raw(Arc::new(State { callback, codec })) as *const (),
+            call: call::<A, R>,
+            drop: drop::<A, R>,
+        }
+    }
+}
+
+impl fmt::Debug for FfiCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FfiCallback")
+    }
+}
+
+impl Drop for FfiCallback {
+    fn drop(&mut self) {
+        // SAFETY: `data` is not used after this.
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+type LocalFn<A, R> = dyn Fn(A) -> Pin<Box<dyn Future<Output = R> + Send>> + Send + Sync;
+
+/// The callback passed to the method declared with the `Callback<A, R>` argument in `plugin_api_trait`.
+///
+/// The caller creates it from an async closure with [`Callback::new`], and the implementation
+/// invokes it with [`Callback::call`] any number of times. Multiple arguments can be passed as a tuple.
+/// The arguments and return values are encoded by the codec of the interface.
+///
+/// The callback should not outlive the call, since the caller may be replaced by `Registry::reload` after it.
+pub struct Callback<A, R> {
+    inner: Inner<A, R>,
+}
+
+enum Inner<A, R> {
+    Local(Arc<LocalFn<A, R>>),
+    Remote {
+        callback: Arc<FfiCallback>,
+        codec: Codec,
+    },
+}
+
+impl<A, R> Callback<A, R> {
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+    {
+        Self {
+            inner: Inner::Local(Arc::new(move |args| Box::pin(f(args)))),
+        }
+    }
+
+    pub(crate) fn from_ffi(callback: FfiCallback, codec: Codec) -> Self {
+        Self {
+            inner: Inner::Remote {
+                callback: Arc::new(callback),
+                codec,
+            },
+        }
+    }
+}
+
+impl<A: Serialize, R: DeserializeOwned> Callback<A, R> {
+    /// Invoke the callback, which may run in the caller across the boundary.
+    pub async fn call(&self, args: A) -> Result<R, Error> {
+        match &self.inner {
+            Inner::Local(f) => Ok(f(args).await),
+            Inner::Remote { callback, codec } => {
+                let args = codec.encode(&args).map_err(Error::Serialize)?;
+                // SAFETY: `data` is valid until `drop` is called, and the future holds its own reference.
+                let ret = unsafe { (callback.call)(callback.data, args.into()) }
+                    .await
+                    .into_result()?;
+                codec.decode(&ret).map_err(Error::Deserialize)
+            }
+        }
+    }
+}
+
+impl<A, R> Clone for Callback<A, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: match &self.inner {
+                Inner::Local(f) => Inner::Local(f.clone()),
+                Inner::Remote { callback, codec } => Inner::Remote {
+                    callback: callback.clone(),
+                    codec: *codec,
+                },
+            },
+        }
+    }
+}
+
+impl<A, R> fmt::Debug for Callback<A, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.inner {
+            Inner::Local(_) => "Callback::Local",
+            Inner::Remote { .. } => "Callback::Remote",
+        })
+    }
+}
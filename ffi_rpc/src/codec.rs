@@ -0,0 +1,100 @@
+use std::fmt;
+
+use abi_stable::StableAbi;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The format to serialize the arguments and return values, chosen per interface.
+///
+/// Except `MessagePack`, the codecs should be enabled by the cargo features of the same name.
+#[repr(u8)]
+#[derive(StableAbi, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    MessagePack,
+    /// Feature `json`.
+    Json,
+    /// Feature `postcard`.
+    Postcard,
+    /// Feature `bincode`.
+    Bincode,
+    /// Feature `cbor`.
+    Cbor,
+}
+
+/// The codecs enabled by the features, referenced by the interfaces defined with `plugin_api_trait`.
+#[doc(hidden)]
+pub mod enabled {
+    #[cfg(feature = "json")]
+    pub const JSON: super::Codec = super::Codec::Json;
+    #[cfg(feature = "postcard")]
+    pub const POSTCARD: super::Codec = super::Codec::Postcard;
+    #[cfg(feature = "bincode")]
+    pub const BINCODE: super::Codec = super::Codec::Bincode;
+    #[cfg(feature = "cbor")]
+    pub const CBOR: super::Codec = super::Codec::Cbor;
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(self, v: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::MessagePack => rmp_serde::to_vec(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::to_vec(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => postcard::to_stdvec(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => bincode::serde::encode_to_vec(v, bincode::config::standard())
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut ret = Vec::new();
+                ciborium::into_writer(v, &mut ret).map_err(|e| e.to_string())?;
+                Ok(ret)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    /// Same as [`Codec::encode`], except that the structs are encoded as maps by MessagePack.
+    pub fn encode_named<T: Serialize>(self, v: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::MessagePack => rmp_serde::to_vec_named(v).map_err(|e| e.to_string()),
+            _ => self.encode(v),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, v: &[u8]) -> Result<T, String> {
+        match self {
+            Codec::MessagePack => rmp_serde::from_slice(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::from_slice(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => postcard::from_bytes(v).map_err(|e| e.to_string()),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => bincode::serde::decode_from_slice(v, bincode::config::standard())
+                .map(|(ret, _)| ret)
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::from_reader(v).map_err(|e| e.to_string()),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    fn disabled(self) -> String {
+        format!("codec `{self}` is not enabled, please enable the feature of ffi_rpc")
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Codec::MessagePack => "msgpack",
+            Codec::Json => "json",
+            Codec::Postcard => "postcard",
+            Codec::Bincode => "bincode",
+            Codec::Cbor => "cbor",
+        })
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use abi_stable::{
+    std_types::{RDuration, RHashMap, ROption, RString},
+    StableAbi,
+};
+use async_ffi::{ContextExt, FfiContext};
+
+/// The slot of the waker which is not registered yet.
+const NO_SLOT: usize = usize::MAX;
+
+/// The FFI-safe token cancelled when the caller gives up the call, e.g, the future of the method is dropped.
+///
+/// The clones share the same state, so it can be moved into the work spawned by the implementation.
+/// The token should not outlive the call, since it may be created by another library.
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct CancelToken {
+    data: *const (),
+    cancel: unsafe extern "C" fn(*const ()),
+    is_cancelled: unsafe extern "C" fn(*const ()) -> bool,
+    poll_cancelled: unsafe extern "C" fn(*const (), &mut usize, &mut FfiContext<'_>) -> bool,
+    unregister: unsafe extern "C" fn(*const (), usize),
+    clone: unsafe extern "C" fn(*const ()) -> *const (),
+    drop: unsafe extern "C" fn(*const ()),
+}
+
+// SAFETY: the state is `Arc<State>`, which is `Send` and `Sync`.
+unsafe impl Send for CancelToken {}
+unsafe impl Sync for CancelToken {}
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+    /// The wakers of the pending `Cancelled` futures, indexed by their slots.
+    wakers: Mutex<Vec<Option<Waker>>>,
+    /// The parent token and the slot of `Link` registered in it.
+    parent: OnceLock<(CancelToken, usize)>,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        if let Some((parent, slot)) = self.parent.take() {
+            // SAFETY: `data` is valid until `drop` is called.
+            unsafe { (parent.unregister)(parent.data, slot) }
+        }
+    }
+}
+
+/// The waker registered in the parent token, which cancels the child.
+struct Link(Weak<State>);
+
+impl Wake for Link {
+    fn wake(self: Arc<Self>) {
+        if let Some(state) = self.0.upgrade() {
+            state.cancel();
+        }
+    }
+}
+
+impl State {
+    fn wakers(&self) -> MutexGuard<'_, Vec<Option<Waker>>> {
+        self.wakers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.wakers());
+        wakers.into_iter().flatten().for_each(Waker::wake);
+    }
+
+    fn poll(&self, slot: &mut usize, waker: &Waker) -> bool {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+        let mut wakers = self.wakers();
+        match wakers.get_mut(*slot) {
+            Some(x) => *x = Some(waker.clone()),
+            None => {
+                *slot = match wakers.iter().position(Option::is_none) {
+                    Some(i) => i,
+                    None => {
+                        wakers.push(None);
+                        wakers.len() - 1
+                    }
+                };
+                wakers[*slot] = Some(waker.clone());
+            }
+        }
+        drop(wakers);
+        // Cancelled before the waker is registered.
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        unsafe extern "C" fn cancel(data: *const ()) {
+            // SAFETY: `data` is the `Arc<State>` created in `CancelToken::new`.
+            let state = unsafe { &*(data as *const State) };
+            // Never unwind across the boundary.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| state.cancel()));
+        }
+
+        unsafe extern "C" fn is_cancelled(data: *const ()) -> bool {
+            // SAFETY: same as above.
+            let state = unsafe { &*(data as *const State) };
+            state.cancelled.load(Ordering::SeqCst)
+        }
+
+        unsafe extern "C" fn poll_cancelled(
+            data: *const (),
+            slot: &mut usize,
+            cx: &mut FfiContext<'_>,
+        ) -> bool {
+            // SAFETY: same as above.
+            let state = unsafe { &*(data as *const State) };
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                cx.with_context(|cx| state.poll(slot, cx.waker()))
+            }))
+            .unwrap_or(true)
+        }
+
+        unsafe extern "C" fn unregister(data: *const (), slot: usize) {
+            // SAFETY: same as above.
+            let state = unsafe { &*(data as *const State) };
+            let waker = state.wakers().get_mut(slot).and_then(Option::take);
+            let _ = panic::catch_unwind(AssertUnwindSafe(move || std::mem::drop(waker)));
+        }
+
+        unsafe extern "C" fn clone(data: *const ()) -> *const () {
+            // SAFETY: same as above.
+            unsafe { Arc::increment_strong_count(data as *const State) };
+            data
+        }
+
+        unsafe extern "C" fn drop(data: *const ()) {
+            // SAFETY: same as above.
+            let state = unsafe { Arc::from_raw(data as *const State) };
+            let _ = panic::catch_unwind(AssertUnwindSafe(move || std::mem::drop(state)));
+        }
+
+        Self {
+            data: Arc::into_raw(Arc::new(State::default())) as *const (),
+            cancel,
+            is_cancelled,
+            poll_cancelled,
+            unregister,
+            clone,
+            drop,
+        }
+    }
+
+    /// Cancel the token and wake the tasks waiting for it.
+    pub fn cancel(&self) {
+        // SAFETY: `data` is valid until `drop` is called.
+        unsafe { (self.cancel)(self.data) }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        // SAFETY: same as above.
+        unsafe { (self.is_cancelled)(self.data) }
+    }
+
+    /// Wait until the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            slot: NO_SLOT,
+        }
+    }
+
+    /// Create a new token which is also cancelled when this token is cancelled.
+    pub fn child(&self) -> Self {
+        let ret = Self::new();
+        // SAFETY: `data` is the `Arc<State>` created in `CancelToken::new`.
+        let state = unsafe {
+            Arc::increment_strong_count(ret.data as *const State);
+            Arc::from_raw(ret.data as *const State)
+        };
+        let waker = Waker::from(Arc::new(Link(Arc::downgrade(&state))));
+        let mut slot = NO_SLOT;
+        // SAFETY: `data` is valid until `drop` is called.
+        let cancelled = Context::from_waker(&waker)
+            .with_ffi_context(|cx| unsafe { (self.poll_cancelled)(self.data, &mut slot, cx) });
+        if cancelled {
+            state.cancel();
+        }
+        let _ = state.parent.set((self.clone(), slot));
+        ret
+    }
+
+    /// Cancel the token when the guard is dropped, unless it is disarmed.
+    pub fn drop_guard(self) -> DropGuard {
+        DropGuard(Some(self))
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for CancelToken {
+    fn clone(&self) -> Self {
+        Self {
+            // SAFETY: same as above.
+            data: unsafe { (self.clone)(self.data) },
+            ..*self
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Drop for CancelToken {
+    fn drop(&mut self) {
+        // SAFETY: `data` is not used after this.
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+/// Future returned by [`CancelToken::cancelled`].
+pub struct Cancelled<'a> {
+    token: &'a CancelToken,
+    slot: usize,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let token = this.token;
+        // SAFETY: `data` is valid until `drop` is called.
+        if cx.with_ffi_context(|cx| unsafe {
+            (token.poll_cancelled)(token.data, &mut this.slot, cx)
+        }) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if self.slot != NO_SLOT {
+            // SAFETY: same as above.
+            unsafe { (self.token.unregister)(self.token.data, self.slot) }
+        }
+    }
+}
+
+/// Returned by [`CancelToken::drop_guard`].
+#[derive(Debug)]
+pub struct DropGuard(Option<CancelToken>);
+
+impl DropGuard {
+    /// Keep the token uncancelled, e.g, the call is finished.
+    pub fn disarm(mut self) -> CancelToken {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+thread_local! {
+    /// The context of the call being polled in this thread.
+    static CURRENT: Cell<*const CallContext> = const { Cell::new(std::ptr::null()) };
+}
+
+/// The context passed with each call in `Request`.
+///
+/// The implementation gets it by [`CallContext::current`] while the method is polled,
+/// the work spawned by the implementation should take a clone of it.
+///
+/// The calls made by the implementation forward the context automatically: the metadata and the deadline
+/// are copied, and the token is a child of the current one. The host can start the chain with [`CallContext::scope`].
+#[repr(C)]
+#[derive(StableAbi, Debug, Clone, Default)]
+pub struct CallContext {
+    /// Cancelled when the caller gives up the call, including the deadline exceeded.
+    pub cancel: CancelToken,
+    /// The deadline set by the caller with `with_timeout`, since the UNIX epoch.
+    pub deadline: ROption<RDuration>,
+    /// The key/value pairs set by the caller, e.g, the request id and the trace context.
+    pub metadata: RHashMap<RString, RString>,
+}
+
+impl CallContext {
+    /// Get the context of the call being polled in this thread,
+    /// or a new context if it is not called by `_ffi_invoke`.
+    /// ```ignore
+    /// async fn add(&self, _: &Registry, a: i32, b: i32) -> i32 {
+    ///     let cancel = CallContext::current().cancel;
+    ///     tokio::spawn(async move {
+    ///         cancel.cancelled().await;
+    ///         println!("the caller gave up");
+    ///     });
+    ///     a + b
+    /// }
+    /// ```
+    pub fn current() -> Self {
+        CURRENT.with(|x| {
+            let ctx = x.get();
+            if ctx.is_null() {
+                Self::default()
+            } else {
+                // SAFETY: the pointer is set by `Scope` only during its poll.
+                unsafe { (*ctx).clone() }
+            }
+        })
+    }
+
+    /// Get the metadata by the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(RString::as_str)
+    }
+
+    /// Set the metadata, which is forwarded to the nested calls.
+    pub fn insert<K: Into<RString>, V: Into<RString>>(&mut self, key: K, value: V) {
+        self.metadata.insert(key.into(), value.into());
+    }
+
+    /// Same as [`CallContext::insert`], but takes and returns `self`.
+    pub fn with_metadata<K: Into<RString>, V: Into<RString>>(mut self, key: K, value: V) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Create the context of the call made during this call.
+    pub fn child(&self) -> Self {
+        Self {
+            cancel: self.cancel.child(),
+            deadline: self.deadline,
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Set the deadline after `timeout`, unless the current deadline is earlier.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        let deadline = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_add(timeout);
+        if self
+            .deadline
+            .into_option()
+            .is_none_or(|x| Duration::from(x) > deadline)
+        {
+            self.deadline = ROption::RSome(deadline.into());
+        }
+    }
+
+    /// Get the time left before the deadline, zero if it is exceeded, `None` if there is no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = Duration::from(self.deadline.into_option()?);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Some(deadline.saturating_sub(now))
+    }
+
+    /// Make the context current while running `f`, see [`CallContext::scope`].
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        /// Restore the outer context even if `f` panics.
+        struct Reset(*const CallContext);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                CURRENT.with(|x| x.set(self.0));
+            }
+        }
+
+        let _reset = Reset(CURRENT.with(|x| x.replace(self)));
+        f()
+    }
+
+    /// Make the context current while polling the future, so the calls made in it forward the context.
+    /// ```ignore
+    /// let ctx = CallContext::default().with_metadata("request-id", "42");
+    /// ctx.scope(async { client.add(&r, &1, &2).await }).await;
+    /// ```
+    pub fn scope<F: Future>(self, fut: F) -> Scope<F> {
+        Scope { ctx: self, fut }
+    }
+}
+
+/// Future returned by [`CallContext::scope`].
+pub struct Scope<F> {
+    ctx: CallContext,
+    fut: F,
+}
+
+impl<F: Future> Future for Scope<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is never moved out of the pinned `Scope`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        this.ctx.enter(|| fut.poll(cx))
+    }
+}
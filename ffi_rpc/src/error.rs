@@ -0,0 +1,207 @@
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use abi_stable::{
+    library::LibraryError,
+    std_types::{ROption, RResult, RString, RVec},
+    StableAbi,
+};
+
+/// Errors returned when loading the library or calling the `try_*` methods of the API struct.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to load the library.
+    Library(LibraryError),
+    /// The plugin is not found in the registry.
+    NotFound(String),
+    /// The library to reload is the one already loaded, which is opened from the same path.
+    AlreadyLoaded(String),
+    /// Failed to read the plugin directory or manifest.
+    Manifest(String),
+    /// The dependency of the plugin is not registered.
+    MissingDependency(String),
+    /// The plugins depend on each other.
+    CyclicDependency(Vec<String>),
+    /// The library does not implement the interface or the fingerprint mismatches.
+    Incompatible {
+        interface: String,
+        expected: u64,
+        found: Option<u64>,
+    },
+    /// Failed to serialize the arguments.
+    Serialize(String),
+    /// Failed to deserialize the return value.
+    Deserialize(String),
+    /// The function is not defined in the library.
+    UnknownFunction(String),
+    /// The library can not deserialize the arguments.
+    InvalidParams(String),
+    /// The implementation failed, `payload` is the optional data attached by the library.
+    Failed {
+        message: String,
+        payload: Option<Vec<u8>>,
+    },
+    /// The library panicked during the call.
+    Panic(String),
+    /// A plugin with the same id is already registered.
+    AlreadyRegistered(String),
+    /// The function is not finished before the timeout set by `with_timeout`.
+    Timeout(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Library(e) => write!(f, "library error: {e}"),
+            Error::NotFound(id) => write!(f, "plugin `{id}` is not found in the registry"),
+            Error::AlreadyLoaded(path) => write!(
+                f,
+                "library `{path}` is already loaded, reload from a copy with a different path"
+            ),
+            Error::Manifest(e) => write!(f, "manifest error: {e}"),
+            Error::MissingDependency(dep) => write!(f, "dependency {dep} is not registered"),
+            Error::CyclicDependency(ids) => {
+                write!(f, "cyclic dependency between `{}`", ids.join("`, `"))
+            }
+            Error::Incompatible {
+                interface,
+                found: None,
+                ..
+            } => write!(f, "interface `{interface}` is not implemented by the library"),
+            Error::Incompatible {
+                interface,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "interface `{interface}` is incompatible, expected fingerprint {expected:#018x}, found {found:#018x}"
+            ),
+            Error::Serialize(e) => write!(f, "serialize error: {e}"),
+            Error::Deserialize(e) => write!(f, "deserialize error: {e}"),
+            Error::UnknownFunction(func) => {
+                write!(f, "function `{func}` is not defined in the library")
+            }
+            Error::InvalidParams(e) => write!(f, "invalid params: {e}"),
+            Error::Failed { message, .. } => write!(f, "call failed: {message}"),
+            Error::Panic(msg) => write!(f, "library panicked: {msg}"),
+            Error::AlreadyRegistered(id) => write!(f, "plugin `{id}` is already registered"),
+            Error::Timeout(func) => write!(f, "function `{func}` timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<LibraryError> for Error {
+    fn from(e: LibraryError) -> Self {
+        Error::Library(e)
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        let message = e.message.into_string();
+        match e.kind {
+            ErrorKind::UnknownFunction => Error::UnknownFunction(message),
+            ErrorKind::InvalidParams => Error::InvalidParams(message),
+            ErrorKind::Failed => Error::Failed {
+                message,
+                payload: e.payload.into_option().map(RVec::into_vec),
+            },
+            ErrorKind::Panic => Error::Panic(message),
+        }
+    }
+}
+
+/// The kind of [`RpcError`].
+#[repr(u8)]
+#[derive(StableAbi, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The function is not defined in the library.
+    UnknownFunction,
+    /// The arguments can not be deserialized.
+    InvalidParams,
+    /// The implementation failed.
+    Failed,
+    /// The library panicked during the call.
+    Panic,
+}
+
+/// The error envelope transferred through the FFI boundary.
+#[repr(C)]
+#[derive(StableAbi, Debug, Clone)]
+pub struct RpcError {
+    pub kind: ErrorKind,
+    pub message: RString,
+    pub payload: ROption<RVec<u8>>,
+}
+
+impl RpcError {
+    pub fn new<S: Into<RString>>(kind: ErrorKind, message: S) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            payload: ROption::RNone,
+        }
+    }
+
+    /// Attach the payload to the error.
+    pub fn with_payload<P: Into<RVec<u8>>>(mut self, payload: P) -> Self {
+        self.payload = ROption::RSome(payload.into());
+        self
+    }
+}
+
+/// The reply of `PluginApi::call`.
+pub type Reply = RResult<RVec<u8>, RpcError>;
+
+/// Get the message from a panic payload.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Future returned by [`catch_unwind`].
+pub struct CatchUnwind<F> {
+    fut: F,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is never moved out of the pinned `CatchUnwind`.
+        let fut = unsafe { self.map_unchecked_mut(|x| &mut x.fut) };
+        match panic::catch_unwind(AssertUnwindSafe(|| fut.poll(cx))) {
+            Ok(Poll::Ready(v)) => Poll::Ready(Ok(v)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(panic_message(&*e))),
+        }
+    }
+}
+
+/// Catch the panic in each poll and return the panic message.
+pub fn catch_unwind<F: Future>(fut: F) -> CatchUnwind<F> {
+    CatchUnwind { fut }
+}
+
+/// Catch the panic in each poll and reply with [`ErrorKind::Panic`].
+pub async fn catch_panic<T, F: Future<Output = RResult<T, RpcError>>>(
+    fut: F,
+) -> RResult<T, RpcError> {
+    match catch_unwind(fut).await {
+        Ok(ret) => ret,
+        Err(msg) => RResult::RErr(RpcError::new(ErrorKind::Panic, msg)),
+    }
+}
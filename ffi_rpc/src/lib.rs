@@ -1,13 +1,27 @@
 //! Use FFI with RPC! The ABI is stable, any serializable type can be safely transferred through the FFI boundary.
 //!
 //! Please refer to our [crate.io](https://crates.io/crates/ffi_rpc) and [Github](https://github.com/MXWXZ/ffi_rpc) for more documents.
+pub mod bytes;
+pub mod callback;
+pub mod codec;
+pub mod context;
+pub mod error;
+#[cfg(feature = "loader")]
+pub mod loader;
 pub mod plugin;
 pub mod registry;
+pub mod request;
+pub mod stream;
+pub mod timer;
+
+pub use error::Error;
 
 pub use abi_stable;
 pub use async_ffi;
 pub use async_trait;
 pub use ffi_rpc_macro;
+pub use futures_core;
 pub use rmp_serde;
+pub use serde;
 #[cfg(feature = "tokio")]
 pub use tokio;
@@ -0,0 +1,216 @@
+use std::{
+    env::consts::{DLL_PREFIX, DLL_SUFFIX},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    plugin::{load_plugin, Dependency, Plugin},
+    registry::Registry,
+    Error,
+};
+
+/// Get the file name of the dynamic library, e.g, `libclient.so` for `client` on Linux.
+pub fn library_name(name: &str) -> String {
+    format!("{}{}{}", DLL_PREFIX, name, DLL_SUFFIX)
+}
+
+/// The plugin to load.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginEntry {
+    /// Id in the registry.
+    pub id: String,
+    /// Path of the library, relative to the manifest.
+    /// Defaults to [`library_name`] of the id in [`Manifest::dir`].
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Config passed to the `on_load` hook and the instance, `()` if not set.
+    #[serde(default)]
+    pub config: Value,
+}
+
+/// The manifest listing the plugins, in TOML or JSON.
+/// ```toml
+/// dir = "./target/debug"
+///
+/// [[plugin]]
+/// id = "client1"
+/// config = 1
+///
+/// [[plugin]]
+/// id = "client2"
+/// path = "./plugins/libclient2.so"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Manifest {
+    /// Directory of the libraries without path, relative to the manifest.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    #[serde(default, rename = "plugin")]
+    pub plugins: Vec<PluginEntry>,
+}
+
+impl Manifest {
+    /// Read the manifest, the format is detected by the extension (`.json` or `.toml`).
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Manifest(format!("{}: {e}", path.display())))?;
+        let mut manifest: Manifest = match path.extension().and_then(|x| x.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(|e| e.to_string()),
+            Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+            _ => Err("expected `.json` or `.toml` extension".to_string()),
+        }
+        .map_err(|e| Error::Manifest(format!("{}: {e}", path.display())))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        let dir = base.join(manifest.dir.take().unwrap_or_default());
+        for plugin in manifest.plugins.iter_mut() {
+            plugin.path = Some(match plugin.path.take() {
+                Some(path) => base.join(path),
+                None => dir.join(library_name(&plugin.id)),
+            });
+        }
+        manifest.dir = Some(dir);
+        Ok(manifest)
+    }
+
+    /// Scan the directory for the dynamic libraries, the id is the library name, e.g, `client` for `libclient.so`.
+    pub fn from_dir(dir: &Path) -> Result<Self, Error> {
+        let mut plugins = Vec::new();
+        for entry in
+            fs::read_dir(dir).map_err(|e| Error::Manifest(format!("{}: {e}", dir.display())))?
+        {
+            let path = entry
+                .map_err(|e| Error::Manifest(format!("{}: {e}", dir.display())))?
+                .path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.strip_prefix(DLL_PREFIX))
+                .and_then(|x| x.strip_suffix(DLL_SUFFIX))
+            else {
+                continue;
+            };
+            if path.is_file() {
+                plugins.push(PluginEntry {
+                    id: id.to_string(),
+                    path: Some(path.clone()),
+                    config: Value::Null,
+                });
+            }
+        }
+        plugins.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Self {
+            dir: Some(dir.to_path_buf()),
+            plugins,
+        })
+    }
+
+    /// Load all the plugins into the registry in the topological order of their dependencies.
+    ///
+    /// The dependencies can be provided by the plugins in the manifest or already in the registry.
+    /// The plugins that fail to load, miss dependencies or depend on each other are skipped
+    /// without calling `on_load`, and returned with their ids.
+    /// Note that the interfaces are not checked since they are unknown to the loader.
+    pub fn load(&self, reg: &Registry) -> Result<(), Vec<(String, Error)>> {
+        let mut errors = Vec::new();
+        let mut opened = Vec::new();
+        for plugin in &self.plugins {
+            let path = match &plugin.path {
+                Some(path) => path.clone(),
+                None => self
+                    .dir
+                    .clone()
+                    .unwrap_or_default()
+                    .join(library_name(&plugin.id)),
+            };
+            match load_plugin(&path, &[]) {
+                Ok(api) => opened.push((plugin, api)),
+                Err(e) => errors.push((plugin.id.clone(), e)),
+            }
+        }
+        if let Err(e) = register_all(reg, opened) {
+            errors.extend(e);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Register the opened plugins in the topological order of their dependencies, see [`Manifest::load`].
+///
+/// The path of the entries is ignored, so the mocks can be registered in the same way.
+pub fn register_all(
+    reg: &Registry,
+    plugins: Vec<(&PluginEntry, Plugin)>,
+) -> Result<(), Vec<(String, Error)>> {
+    let mut errors = Vec::new();
+    let mut pending: Vec<_> = plugins
+        .into_iter()
+        .map(|(plugin, api)| {
+            // Copy the dependencies, which outlive the library if it is skipped.
+            let deps = api.api().dependencies()().to_vec();
+            (plugin, api, deps)
+        })
+        .collect();
+
+    loop {
+        // Skip the plugins whose dependencies can never be provided.
+        let missing = |pending: &[Pending], deps: &[Dependency]| {
+            deps.iter()
+                .find(|dep| !reg.provides(dep) && !pending.iter().any(|x| provides(x, dep)))
+                .cloned()
+        };
+        if let Some((i, dep)) = pending
+            .iter()
+            .enumerate()
+            .find_map(|(i, x)| missing(&pending, &x.2).map(|dep| (i, dep)))
+        {
+            let (plugin, _, _) = pending.remove(i);
+            errors.push((plugin.id.clone(), Error::MissingDependency(dep.to_string())));
+            continue;
+        }
+
+        // Register the first plugin whose dependencies are all registered.
+        let Some(i) = pending
+            .iter()
+            .position(|(_, _, deps)| deps.iter().all(|dep| reg.provides(dep)))
+        else {
+            break;
+        };
+        let (plugin, api, _) = pending.remove(i);
+        if let Err(e) = reg.register(plugin.id.clone(), api, &plugin.config) {
+            errors.push((plugin.id.clone(), e));
+        }
+    }
+
+    // The rest depend on each other.
+    let ids: Vec<_> = pending.iter().map(|(x, _, _)| x.id.clone()).collect();
+    for (plugin, _, _) in pending {
+        errors.push((plugin.id.clone(), Error::CyclicDependency(ids.clone())));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The opened library waiting for its dependencies.
+type Pending<'a> = (&'a PluginEntry, Plugin, Vec<Dependency>);
+
+/// Check whether the pending plugin provides the dependency.
+fn provides((plugin, api, _): &Pending, dep: &Dependency) -> bool {
+    match dep {
+        Dependency::Plugin(id) => plugin.id == id.as_str(),
+        Dependency::Interface { path, fingerprint } => api.api().implements(path, *fingerprint),
+    }
+}
@@ -1,27 +1,208 @@
-use std::path::Path;
+use std::{
+    fmt,
+    future::poll_fn,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    task::{Poll, Waker},
+};
 
 use abi_stable::{
-    library::{lib_header_from_path, LibraryError, RootModule},
+    external_types::{RMutex, RRwLock},
+    library::{lib_header_from_raw_library, RawLibrary, RootModule},
     package_version_strings,
     sabi_types::VersionStrings,
-    std_types::{RString, RVec},
+    std_types::{RArc, ROption, RResult, RStr, RString, RVec},
     StableAbi,
 };
 use async_ffi::BorrowingFfiFuture;
+use serde::de::DeserializeOwned;
 
-use crate::registry::Registry;
+use crate::{
+    codec::Codec,
+    error::{panic_message, ErrorKind, Reply, RpcError},
+    registry::Registry,
+    request::Request,
+    stream::StreamReply,
+    Error,
+};
 
 #[repr(C)]
 #[derive(StableAbi)]
 #[sabi(kind(Prefix(prefix_ref = PluginApiRef)))]
 #[sabi(missing_field(panic))]
 pub struct PluginApi {
-    #[sabi(last_prefix_field)]
     pub call: for<'fut> extern "C" fn(
         RString,
         &'fut Registry,
         RVec<u8>,
-    ) -> BorrowingFfiFuture<'fut, RVec<u8>>,
+    ) -> BorrowingFfiFuture<'fut, Reply>,
+    /// Get the fingerprint of the implemented interface by its path.
+    pub fingerprint: extern "C" fn(RStr<'_>) -> ROption<u64>,
+    /// List all the implemented interfaces.
+    pub interfaces: extern "C" fn() -> RVec<InterfaceInfo>,
+    /// Called with the serialized config before the plugin is registered.
+    pub on_load: extern "C" fn(&Registry, RVec<u8>) -> RResult<(), RpcError>,
+    /// Called when the registry shuts down.
+    pub on_unload: extern "C" fn() -> RResult<(), RpcError>,
+    /// List the plugins or interfaces required to be registered before this plugin.
+    pub dependencies: extern "C" fn() -> RVec<Dependency>,
+    /// Same as `call`, except that some arguments are passed as the attachments without the codec.
+    pub invoke:
+        for<'fut> extern "C" fn(&'fut Registry, Request<'fut>) -> BorrowingFfiFuture<'fut, Reply>,
+    /// Same as `invoke`, except that the method returns a stream.
+    pub invoke_stream: for<'fut> extern "C" fn(
+        &'fut Registry,
+        Request<'fut>,
+    ) -> BorrowingFfiFuture<'fut, StreamReply>,
+    /// Same as `invoke`, except that the sync method is called directly without the executor.
+    #[sabi(last_prefix_field)]
+    pub invoke_sync: for<'a> extern "C" fn(&'a Registry, Request<'a>) -> Reply,
+}
+
+/// Default lifecycle hooks, which are shadowed by `plugin_impl_on_load`, `plugin_impl_on_unload`,
+/// `plugin_impl_requires` and `plugin_impl_instance` with the config.
+#[doc(hidden)]
+pub mod hooks {
+    use abi_stable::{
+        sabi_extern_fn,
+        std_types::{RResult, RVec},
+    };
+
+    use crate::{error::RpcError, plugin::Dependency, registry::Registry};
+
+    pub fn _ffi_init(_: &[u8]) -> Result<(), RpcError> {
+        Ok(())
+    }
+
+    #[sabi_extern_fn]
+    pub fn _ffi_on_load(_: &Registry, _: RVec<u8>) -> RResult<(), RpcError> {
+        RResult::ROk(())
+    }
+
+    #[sabi_extern_fn]
+    pub fn _ffi_on_unload() -> RResult<(), RpcError> {
+        RResult::ROk(())
+    }
+
+    #[sabi_extern_fn]
+    pub fn _ffi_dependencies() -> RVec<Dependency> {
+        RVec::new()
+    }
+}
+
+/// The interface defined by `plugin_api_trait`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interface {
+    /// Full path of the trait.
+    pub path: &'static str,
+    /// Computed from the method names, argument/return types and the codec.
+    pub fingerprint: u64,
+    /// Serialize the arguments and return values.
+    pub codec: Codec,
+    /// Whether the arguments are encoded as a map by their names.
+    pub named: bool,
+    pub methods: &'static [Method],
+}
+
+/// The method defined in the interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Method {
+    /// Full path of the method, which is the function name passed to `PluginApi::call`.
+    pub name: &'static str,
+    /// Signature in the interface, e.g, `async fn add(a: i32, b: i32) -> i32`.
+    pub signature: &'static str,
+}
+
+/// FFI-safe version of [`Interface`] returned by `PluginApi::interfaces`.
+#[repr(C)]
+#[derive(StableAbi, Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub path: RString,
+    pub fingerprint: u64,
+    pub codec: Codec,
+    pub named: bool,
+    pub methods: RVec<MethodInfo>,
+}
+
+/// FFI-safe version of [`Method`].
+#[repr(C)]
+#[derive(StableAbi, Debug, Clone, PartialEq, Eq)]
+pub struct MethodInfo {
+    pub name: RString,
+    pub signature: RString,
+}
+
+/// The dependency declared by `plugin_impl_requires`.
+#[repr(u8)]
+#[derive(StableAbi, Debug, Clone, PartialEq, Eq)]
+pub enum Dependency {
+    /// The plugin registered with the id.
+    Plugin(RString),
+    /// Any plugin implementing the interface with the same fingerprint.
+    Interface { path: RString, fingerprint: u64 },
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dependency::Plugin(id) => write!(f, "plugin `{id}`"),
+            Dependency::Interface { path, .. } => write!(f, "interface `{path}`"),
+        }
+    }
+}
+
+impl From<&Interface> for Dependency {
+    fn from(v: &Interface) -> Self {
+        Dependency::Interface {
+            path: v.path.into(),
+            fingerprint: v.fingerprint,
+        }
+    }
+}
+
+impl From<&Interface> for InterfaceInfo {
+    fn from(v: &Interface) -> Self {
+        Self {
+            path: v.path.into(),
+            fingerprint: v.fingerprint,
+            codec: v.codec,
+            named: v.named,
+            methods: v.methods.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&Method> for MethodInfo {
+    fn from(v: &Method) -> Self {
+        Self {
+            name: v.name.into(),
+            signature: v.signature.into(),
+        }
+    }
+}
+
+impl PluginApiRef {
+    /// Check that all the interfaces are implemented by the library with the same fingerprint.
+    pub fn check_interfaces(&self, interfaces: &[Interface]) -> Result<(), Error> {
+        for interface in interfaces {
+            let found = self.fingerprint()(interface.path.into()).into_option();
+            if found != Some(interface.fingerprint) {
+                return Err(Error::Incompatible {
+                    interface: interface.path.to_string(),
+                    expected: interface.fingerprint,
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the library implements the interface with the fingerprint.
+    pub fn implements(&self, path: &str, fingerprint: u64) -> bool {
+        self.fingerprint()(path.into()).into_option() == Some(fingerprint)
+    }
 }
 
 /// The RootModule trait defines how to load the root module of a library.
@@ -33,6 +214,237 @@ impl RootModule for PluginApiRef {
     const VERSION_STRINGS: VersionStrings = package_version_strings!();
 }
 
-pub fn load_plugin(path: &Path) -> Result<PluginApiRef, LibraryError> {
-    lib_header_from_path(path).and_then(|x| x.init_root_module::<PluginApiRef>())
+/// The implementation instance created from the config passed by the host when loading.
+///
+/// Defined by `plugin_impl_instance` with a one-argument closure.
+pub struct Instance<T>(OnceLock<T>);
+
+impl<T> Instance<T> {
+    pub const fn new() -> Self {
+        Self(OnceLock::new())
+    }
+
+    /// Get the instance, `None` if the plugin is not loaded yet.
+    pub fn get(&self) -> Option<&T> {
+        self.0.get()
+    }
+
+    /// Deserialize the config and create the instance with `f`.
+    pub fn init<C: DeserializeOwned>(
+        &self,
+        config: &[u8],
+        f: impl FnOnce(C) -> T,
+    ) -> Result<(), RpcError> {
+        let config = rmp_serde::from_slice(config)
+            .map_err(|e| RpcError::new(ErrorKind::InvalidParams, e.to_string()))?;
+        let instance = panic::catch_unwind(AssertUnwindSafe(|| f(config)))
+            .map_err(|e| RpcError::new(ErrorKind::Panic, panic_message(&*e)))?;
+        self.0
+            .set(instance)
+            .map_err(|_| RpcError::new(ErrorKind::Failed, "the instance is already initialized"))
+    }
+}
+
+impl<T> Default for Instance<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for Instance<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+            .get()
+            .expect("the instance is not initialized, the plugin is not loaded")
+    }
+}
+
+/// The loaded library, whose `on_unload` is called when the last reference is dropped after reloading.
+#[repr(C)]
+#[derive(StableAbi)]
+pub(crate) struct Loaded {
+    pub(crate) api: PluginApiRef,
+    /// Set by `Registry::reload`, then `on_unload` is called when the last reference is dropped.
+    retired: RMutex<ROption<RArc<Drained>>>,
+}
+
+/// Notified when the retired library is drained and `on_unload` is called.
+#[repr(C)]
+#[derive(StableAbi, Default)]
+pub(crate) struct Drained {
+    #[sabi(unsafe_opaque_field)]
+    state: Mutex<DrainState>,
+}
+
+#[derive(Default)]
+struct DrainState {
+    /// The result of `on_unload`.
+    result: Option<RResult<(), RpcError>>,
+    waker: Option<Waker>,
+}
+
+impl Drained {
+    /// Wait for the drain and return the result of `on_unload`.
+    pub(crate) async fn wait(&self) -> RResult<(), RpcError> {
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            match state.result.take() {
+                Some(ret) => Poll::Ready(ret),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn finish(&self, ret: RResult<(), RpcError>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(ret);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Loaded {
+    /// Open the library, which is leaked and never unloaded.
+    ///
+    /// The layout checker keeps references to the type layouts in the library,
+    /// and the tasks spawned by it may outlive the drain of `Registry::reload`.
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        let library: &'static RawLibrary = Box::leak(Box::new(RawLibrary::load_at(path)?));
+        // SAFETY: the library is never unloaded.
+        let api =
+            unsafe { lib_header_from_raw_library(library) }?.init_root_module::<PluginApiRef>()?;
+        Ok(Self::new(api))
+    }
+
+    fn new(api: PluginApiRef) -> Self {
+        Self {
+            api,
+            retired: RMutex::new(ROption::RNone),
+        }
+    }
+
+    /// Call `on_unload` and notify `drained` when the last reference is dropped.
+    pub(crate) fn retire(&self, drained: RArc<Drained>) {
+        *self.retired.lock() = ROption::RSome(drained);
+    }
+}
+
+impl Drop for Loaded {
+    fn drop(&mut self) {
+        if let ROption::RSome(drained) = self.retired.get_mut().take() {
+            drained.finish(self.api.on_unload()());
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(StableAbi)]
+struct PluginInner {
+    current: RRwLock<RArc<Loaded>>,
+    /// The interfaces implemented by the current library.
+    interfaces: RRwLock<RVec<InterfaceInfo>>,
+    /// The serialized config passed to `on_load`, which is reused when reloading.
+    config: RMutex<RVec<u8>>,
+}
+
+/// Handle of the plugin shared by the registry and the API structs.
+///
+/// The handle always calls the current library after `Registry::reload`,
+/// and the replaced library is not drained until all the handles and in-flight calls to it are dropped.
+#[repr(C)]
+#[derive(StableAbi, Clone)]
+pub struct Plugin {
+    inner: RArc<PluginInner>,
+}
+
+impl Plugin {
+    fn new(loaded: Loaded) -> Self {
+        Self {
+            inner: RArc::new(PluginInner {
+                interfaces: RRwLock::new(loaded.api.interfaces()().to_vec().into()),
+                current: RRwLock::new(RArc::new(loaded)),
+                config: RMutex::new(RVec::new()),
+            }),
+        }
+    }
+
+    /// Get the current library, which is not drained by `Registry::reload` until the guard is dropped.
+    pub fn api(&self) -> PluginGuard {
+        PluginGuard(self.inner.current.read().clone())
+    }
+
+    /// List the interfaces implemented by the current library.
+    pub fn interfaces(&self) -> RVec<InterfaceInfo> {
+        self.inner.interfaces.read().clone()
+    }
+
+    /// Check whether the current library implements the interface with the fingerprint.
+    pub fn implements(&self, path: &str, fingerprint: u64) -> bool {
+        self.inner
+            .interfaces
+            .read()
+            .iter()
+            .any(|x| x.path == path && x.fingerprint == fingerprint)
+    }
+
+    pub(crate) fn config(&self) -> RVec<u8> {
+        self.inner.config.lock().clone()
+    }
+
+    pub(crate) fn set_config(&self, config: RVec<u8>) {
+        *self.inner.config.lock() = config;
+    }
+
+    /// Swap in the new library and return the old one.
+    pub(crate) fn replace(&self, loaded: Loaded) -> RArc<Loaded> {
+        *self.inner.interfaces.write() = loaded.api.interfaces()().to_vec().into();
+        std::mem::replace(&mut *self.inner.current.write(), RArc::new(loaded))
+    }
+}
+
+/// The root module loaded by other means, e.g, the mock.
+impl From<PluginApiRef> for Plugin {
+    fn from(api: PluginApiRef) -> Self {
+        Self::new(Loaded::new(api))
+    }
+}
+
+/// Implemented by the API struct defined with `plugin_api_struct`.
+pub trait ApiStruct: From<Plugin> {
+    /// The interfaces passed to `plugin_api_struct`.
+    const INTERFACES: &'static [Interface];
+}
+
+/// Implemented by the API struct declared with `plugin_api_struct(id = "...")`.
+pub trait DefaultId: ApiStruct {
+    /// The default id in the registry.
+    const ID: &'static str;
+}
+
+/// Delay `on_unload` of the library replaced by `Registry::reload` until the call finishes.
+pub struct PluginGuard(RArc<Loaded>);
+
+impl Deref for PluginGuard {
+    type Target = PluginApiRef;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.api
+    }
+}
+
+/// Load the library and check the interfaces it should implement.
+pub fn load_plugin(path: &Path, interfaces: &[Interface]) -> Result<Plugin, Error> {
+    let loaded = Loaded::open(path)?;
+    loaded.api.check_interfaces(interfaces)?;
+    Ok(Plugin::new(loaded))
 }
@@ -1,18 +1,262 @@
+use std::{path::Path, time::Duration};
+
 use abi_stable::{
-    std_types::{RHashMap, RString},
+    external_types::RRwLock,
+    std_types::{RArc, RDuration, RHashMap, RString, RVec},
     StableAbi,
 };
+use async_ffi::FfiFuture;
+use serde::Serialize;
 
-use crate::plugin::PluginApiRef;
+use crate::{
+    plugin::{
+        ApiStruct, DefaultId, Dependency, Drained, InterfaceInfo, Loaded, MethodInfo, Plugin,
+        PluginApiRef,
+    },
+    timer, Error,
+};
 
+/// The registry shared by the host and the plugins.
+///
+/// It is cheap to clone and all the clones share the same plugins.
+/// The plugins can be registered and removed through `&Registry` at any time, even during the calls.
+///
+/// It also provides the timer of the library creating it, see [`crate::timer::timeout`].
 #[repr(C)]
-#[derive(StableAbi, Default, Clone)]
+#[derive(StableAbi, Clone)]
 pub struct Registry {
-    pub item: RHashMap<RString, PluginApiRef>,
+    inner: RArc<RRwLock<RegistryInner>>,
+    sleep: extern "C" fn(RDuration) -> FfiFuture<()>,
+}
+
+#[repr(C)]
+#[derive(StableAbi, Default)]
+struct RegistryInner {
+    item: RHashMap<RString, Plugin>,
+    /// Registration order of the plugins.
+    order: RVec<RString>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            inner: RArc::new(RRwLock::new(RegistryInner::default())),
+            sleep: timer::sleep,
+        }
+    }
 }
 
 impl Registry {
-    pub fn get(&self, id: &str) -> Option<PluginApiRef> {
-        self.item.get(id).cloned()
+    pub fn get(&self, id: &str) -> Option<Plugin> {
+        self.inner.read().item.get(id).cloned()
+    }
+
+    /// Get the plugin as the API struct, which should implement all the interfaces of the API struct.
+    pub fn get_as<T: ApiStruct>(&self, id: &str) -> Result<T, Error> {
+        let plugin = self
+            .get(id)
+            .ok_or_else(|| Error::NotFound(id.to_string()))?;
+        plugin.api().check_interfaces(T::INTERFACES)?;
+        Ok(T::from(plugin))
+    }
+
+    /// Get the plugin with the default id of the API struct, see [`Registry::get_as`].
+    pub fn get_default<T: DefaultId>(&self) -> Result<T, Error> {
+        self.get_as(T::ID)
+    }
+
+    /// Insert the plugin without calling `on_load`, the old one with the same id is returned.
+    pub fn insert<S: Into<String>>(&self, id: S, api: Plugin) -> Option<Plugin> {
+        let id: RString = id.into().into();
+        let mut inner = self.inner.write();
+        inner.order.retain(|x| x != &id);
+        inner.order.push(id.clone());
+        inner.item.insert(id, api).into_option()
+    }
+
+    /// Remove the plugin without calling `on_unload`.
+    pub fn remove(&self, id: &str) -> Option<Plugin> {
+        let mut inner = self.inner.write();
+        inner.order.retain(|x| x != id);
+        inner.item.remove(id).into_option()
+    }
+
+    /// Check the dependencies, call `on_load` of the plugin with the config, then insert it.
+    ///
+    /// The registry is not locked during `on_load`, so the plugin can register other plugins in it.
+    ///
+    /// Fails with [`Error::AlreadyRegistered`] if the id is taken.
+    pub fn register<S: Into<String>, C: Serialize + ?Sized>(
+        &self,
+        id: S,
+        api: Plugin,
+        config: &C,
+    ) -> Result<(), Error> {
+        let id: RString = id.into().into();
+        if self.inner.read().item.contains_key(&id) {
+            return Err(Error::AlreadyRegistered(id.into_string()));
+        }
+        self.check_dependencies(&api.api())?;
+        let config: RVec<u8> = rmp_serde::to_vec(config)
+            .map_err(|e| Error::Serialize(e.to_string()))?
+            .into();
+        api.api().on_load()(self, config.clone()).into_result()?;
+        api.set_config(config);
+
+        let mut inner = self.inner.write();
+        if inner.item.contains_key(&id) {
+            // Registered by someone else during `on_load`, the error of `on_unload` is ignored
+            // since the registration failed anyway.
+            drop(inner);
+            let _ = api.api().on_unload()();
+            return Err(Error::AlreadyRegistered(id.into_string()));
+        }
+        inner.order.push(id.clone());
+        inner.item.insert(id, api);
+        Ok(())
+    }
+
+    /// Replace the library of the plugin, the API structs holding the plugin will call the new one.
+    ///
+    /// The new library is checked to implement the same interfaces and loaded with the same config.
+    /// Then `on_unload` of the old one is called after the in-flight calls are drained.
+    ///
+    /// The future waits for the drain, and `on_unload` is still called after the drain if it is dropped.
+    /// The old library is never unloaded from the process, since the tasks spawned by it may still run.
+    ///
+    /// The new library should have a different path, since opening the path of a loaded library
+    /// returns the loaded one.
+    pub async fn reload(&self, id: &str, path: &Path) -> Result<(), Error> {
+        let plugin = self
+            .get(id)
+            .ok_or_else(|| Error::NotFound(id.to_string()))?;
+        let loaded = Loaded::open(path)?;
+        if loaded.api.0.to_raw_ptr() == plugin.api().0.to_raw_ptr() {
+            return Err(Error::AlreadyLoaded(path.display().to_string()));
+        }
+        for x in plugin.api().interfaces()() {
+            let found = loaded.api.fingerprint()(x.path.as_rstr()).into_option();
+            if found != Some(x.fingerprint) {
+                return Err(Error::Incompatible {
+                    interface: x.path.into_string(),
+                    expected: x.fingerprint,
+                    found,
+                });
+            }
+        }
+        self.check_dependencies(&loaded.api)?;
+        loaded.api.on_load()(self, plugin.config()).into_result()?;
+
+        let drained = RArc::new(Drained::default());
+        plugin.replace(loaded).retire(drained.clone());
+        drained.wait().await.into_result()?;
+        Ok(())
+    }
+
+    /// Check whether the dependency is registered.
+    pub fn provides(&self, dep: &Dependency) -> bool {
+        match dep {
+            Dependency::Plugin(id) => self.inner.read().item.contains_key(id),
+            Dependency::Interface { path, fingerprint } => self
+                .plugins()
+                .iter()
+                .any(|(_, x)| x.implements(path, *fingerprint)),
+        }
+    }
+
+    /// Check that all the dependencies of the library are registered.
+    pub fn check_dependencies(&self, api: &PluginApiRef) -> Result<(), Error> {
+        match api.dependencies()().into_iter().find(|x| !self.provides(x)) {
+            Some(dep) => Err(Error::MissingDependency(dep.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Call `on_unload` of all the plugins in the reverse registration order, then clear the registry.
+    ///
+    /// Since the dependencies are registered first, the plugins are unloaded before their dependencies.
+    ///
+    /// All the plugins are unloaded even if some of them fail, the failed ones are returned.
+    pub fn shutdown(&self) -> Result<(), Vec<(String, Error)>> {
+        let plugins = {
+            let mut inner = self.inner.write();
+            let plugins: Vec<_> = inner
+                .order
+                .iter()
+                .filter_map(|id| inner.item.get(id).map(|x| (id.clone(), x.clone())))
+                .collect();
+            inner.item.clear();
+            inner.order.clear();
+            plugins
+        };
+        let mut errors = Vec::new();
+        for (id, api) in plugins.into_iter().rev() {
+            if let Err(e) = api.api().on_unload()().into_result() {
+                errors.push((id.into_string(), e.into()));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Find the first registered plugin implementing all the interfaces of the API struct.
+    pub fn find<T: ApiStruct>(&self) -> Option<T> {
+        self.find_all::<T>().into_iter().next().map(|(_, x)| x)
+    }
+
+    /// Find all the plugins implementing all the interfaces of the API struct, in the registration order.
+    pub fn find_all<T: ApiStruct>(&self) -> Vec<(String, T)> {
+        self.plugins()
+            .into_iter()
+            .filter(|(_, x)| {
+                T::INTERFACES
+                    .iter()
+                    .all(|i| x.implements(i.path, i.fingerprint))
+            })
+            .map(|(id, x)| (id.into_string(), T::from(x)))
+            .collect()
+    }
+
+    /// Find all the plugins implementing the interface by its full path, in the registration order.
+    pub fn find_all_implementing(&self, path: &str) -> Vec<(String, Plugin)> {
+        self.plugins()
+            .into_iter()
+            .filter(|(_, x)| x.interfaces().iter().any(|i| i.path == path))
+            .map(|(id, x)| (id.into_string(), x))
+            .collect()
+    }
+
+    /// Get the plugins in the registration order, the registry is not locked after return.
+    fn plugins(&self) -> Vec<(RString, Plugin)> {
+        let inner = self.inner.read();
+        inner
+            .order
+            .iter()
+            .filter_map(|id| inner.item.get(id).map(|x| (id.clone(), x.clone())))
+            .collect()
+    }
+
+    /// List the interfaces implemented by the plugin.
+    pub fn interfaces(&self, id: &str) -> Option<Vec<InterfaceInfo>> {
+        self.get(id).map(|x| x.interfaces().into_vec())
+    }
+
+    /// List the dependencies declared by the plugin.
+    pub fn dependencies(&self, id: &str) -> Option<Vec<Dependency>> {
+        self.get(id).map(|x| x.api().dependencies()().to_vec())
+    }
+
+    /// List the methods of all the interfaces implemented by the plugin.
+    pub fn methods(&self, id: &str) -> Option<Vec<MethodInfo>> {
+        self.interfaces(id)
+            .map(|x| x.into_iter().flat_map(|x| x.methods).collect())
+    }
+
+    /// Sleep with the timer of the library creating the registry.
+    pub(crate) fn sleep(&self, duration: Duration) -> FfiFuture<()> {
+        (self.sleep)(duration.into())
     }
 }
@@ -0,0 +1,142 @@
+use abi_stable::{
+    std_types::{RSlice, RString, RVec},
+    StableAbi,
+};
+
+use crate::{
+    bytes::Bytes,
+    callback::{Callback, FfiCallback},
+    codec::Codec,
+    context::CallContext,
+    error::{ErrorKind, RpcError},
+    stream::{FfiStream, Streaming},
+};
+
+/// The call passed to `PluginApi::invoke`.
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct Request<'a> {
+    /// Full path of the method, see `Method::name`.
+    pub func: RString,
+    /// The arguments encoded by the codec, where the attachments are replaced by their indexes.
+    pub param: RVec<u8>,
+    /// The arguments passed without the codec.
+    pub attachments: RVec<Attachment<'a>>,
+    /// The context of the call, see `CallContext::current`.
+    pub context: CallContext,
+}
+
+/// The argument passed without the codec, e.g, `Bytes`, `&[u8]`, `Streaming` and `Callback`.
+#[repr(u8)]
+#[derive(StableAbi, Debug)]
+pub enum Attachment<'a> {
+    /// Moved to the implementation.
+    Owned(RVec<u8>),
+    /// Lent to the implementation during the call.
+    Borrowed(RSlice<'a, u8>),
+    /// Polled by the implementation.
+    Stream(FfiStream),
+    /// Invoked by the implementation.
+    Callback(FfiCallback),
+    /// Already taken by the implementation.
+    Taken,
+}
+
+impl<'a> Request<'a> {
+    pub fn new<S: Into<RString>>(
+        func: S,
+        param: RVec<u8>,
+        attachments: Vec<Attachment<'a>>,
+    ) -> Self {
+        Self {
+            func: func.into(),
+            param,
+            attachments: attachments.into(),
+            context: CallContext::default(),
+        }
+    }
+
+    /// Pass the context of the call.
+    pub fn with_context(mut self, context: CallContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Take the attachment as `Bytes`, the borrowed one is copied.
+    pub fn take_bytes(&mut self, index: u32) -> Result<Bytes, RpcError> {
+        let x = self
+            .attachments
+            .get_mut(index as usize)
+            .ok_or_else(|| missing(index))?;
+        match std::mem::replace(x, Attachment::Taken) {
+            Attachment::Owned(v) => Ok(v.into()),
+            Attachment::Borrowed(v) => Ok(v.as_slice().into()),
+            x => {
+                self.attachments[index as usize] = x;
+                Err(missing(index))
+            }
+        }
+    }
+
+    /// Take the attachment as `Streaming`, the items are decoded by the codec.
+    pub fn take_stream<T>(&mut self, index: u32, codec: Codec) -> Result<Streaming<T>, RpcError> {
+        let x = self
+            .attachments
+            .get_mut(index as usize)
+            .ok_or_else(|| missing(index))?;
+        match std::mem::replace(x, Attachment::Taken) {
+            Attachment::Stream(v) => Ok(Streaming::from_ffi(v, codec, None, None)),
+            x => {
+                self.attachments[index as usize] = x;
+                Err(missing(index))
+            }
+        }
+    }
+
+    /// Take the attachment as `Callback`, the arguments and return values are encoded by the codec.
+    pub fn take_callback<A, R>(
+        &mut self,
+        index: u32,
+        codec: Codec,
+    ) -> Result<Callback<A, R>, RpcError> {
+        let x = self
+            .attachments
+            .get_mut(index as usize)
+            .ok_or_else(|| missing(index))?;
+        match std::mem::replace(x, Attachment::Taken) {
+            Attachment::Callback(v) => Ok(Callback::from_ffi(v, codec)),
+            x => {
+                self.attachments[index as usize] = x;
+                Err(missing(index))
+            }
+        }
+    }
+
+    /// Borrow the attachment as `&[u8]`.
+    pub fn bytes(&self, index: u32) -> Result<&[u8], RpcError> {
+        match self.attachments.get(index as usize) {
+            Some(Attachment::Owned(v)) => Ok(v),
+            Some(Attachment::Borrowed(v)) => Ok(v.as_slice()),
+            _ => Err(missing(index)),
+        }
+    }
+}
+
+fn missing(index: u32) -> RpcError {
+    RpcError::new(
+        ErrorKind::InvalidParams,
+        format!("attachment {index} is missing or mismatched"),
+    )
+}
+
+impl From<Bytes> for Attachment<'_> {
+    fn from(v: Bytes) -> Self {
+        Attachment::Owned(v.into())
+    }
+}
+
+impl<'a> From<&'a [u8]> for Attachment<'a> {
+    fn from(v: &'a [u8]) -> Self {
+        Attachment::Borrowed(v.into())
+    }
+}
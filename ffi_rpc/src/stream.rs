@@ -0,0 +1,316 @@
+use std::{
+    fmt,
+    future::poll_fn,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use abi_stable::{
+    std_types::{ROption, RResult, RVec},
+    StableAbi,
+};
+use async_ffi::{ContextExt, FfiContext, FfiPoll};
+use futures_core::Stream;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::Codec,
+    context::{CallContext, DropGuard},
+    error::{panic_message, ErrorKind, Reply, RpcError},
+    plugin::PluginGuard,
+    Error,
+};
+
+/// The reply of `PluginApi::invoke_stream`.
+pub type StreamReply = RResult<FfiStream, RpcError>;
+
+/// The FFI-safe stream of the encoded items, which is polled by the caller.
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct FfiStream {
+    data: *mut (),
+    poll_next: unsafe extern "C" fn(*mut (), &mut FfiContext<'_>) -> FfiPoll<ROption<Reply>>,
+    drop: unsafe extern "C" fn(*mut ()),
+}
+
+// SAFETY: only created from `Streaming`, which is `Send`.
+unsafe impl Send for FfiStream {}
+
+impl FfiStream {
+    /// Encode the items by the codec, the panics are caught and replied as `ErrorKind::Panic`.
+    ///
+    /// The current context is entered while polling, so the stream returned by the implementation
+    /// runs in the context of the call, and in the tokio runtime of the call with the `tokio` feature.
+    pub fn new<T>(stream: Streaming<T>, codec: Codec) -> Self
+    where
+        T: Serialize + DeserializeOwned + Send + 'static,
+    {
+        struct State<T> {
+            stream: Streaming<T>,
+            codec: Codec,
+            done: bool,
+            context: CallContext,
+            #[cfg(feature = "tokio")]
+            runtime: Option<tokio::runtime::Handle>,
+        }
+
+        unsafe extern "C" fn poll_next<T>(
+            data: *mut (),
+            cx: &mut FfiContext<'_>,
+        ) -> FfiPoll<ROption<Reply>>
+        where
+            T: Serialize + DeserializeOwned + Send + 'static,
+        {
+            // SAFETY: `data` is the `State<T>` boxed in `FfiStream::new`.
+            let state = unsafe { &mut *(data as *mut State<T>) };
+            if state.done {
+                return FfiPoll::Ready(ROption::RNone);
+            }
+            let codec = state.codec;
+            let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+                #[cfg(feature = "tokio")]
+                let _runtime = state.runtime.as_ref().map(|x| x.enter());
+                let stream = &mut state.stream;
+                state
+                    .context
+                    .enter(|| cx.with_context(|cx| stream.poll_reply(cx, codec)))
+            }));
+            match ret {
+                Ok(Poll::Ready(Some(v))) => FfiPoll::Ready(ROption::RSome(v)),
+                Ok(Poll::Ready(None)) => {
+                    state.done = true;
+                    FfiPoll::Ready(ROption::RNone)
+                }
+                Ok(Poll::Pending) => FfiPoll::Pending,
+                Err(e) => {
+                    state.done = true;
+                    FfiPoll::Ready(ROption::RSome(RResult::RErr(RpcError::new(
+                        ErrorKind::Panic,
+                        panic_message(&*e),
+                    ))))
+                }
+            }
+        }
+
+        unsafe extern "C" fn drop<T>(data: *mut ()) {
+            // SAFETY: `data` is the `State<T>` boxed in `FfiStream::new`.
+            let state = unsafe { Box::from_raw(data as *mut State<T>) };
+            // Never unwind across the boundary.
+            let _ = panic::catch_unwind(AssertUnwindSafe(move || std::mem::drop(state)));
+        }
+
+        let state = Box::new(State {
+            stream,
+            codec,
+            done: false,
+            context: CallContext::current(),
+            #[cfg(feature = "tokio")]
+            runtime: tokio::runtime::Handle::try_current().ok(),
+        });
+        Self {
+            data: Box::into_raw(state) as *mut (),
+            poll_next: poll_next::<T>,
+            drop: drop::<T>,
+        }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Reply>> {
+        // SAFETY: `data` is valid until `drop` is called.
+        match cx.with_ffi_context(|cx| unsafe { (self.poll_next)(self.data, cx) }) {
+            FfiPoll::Ready(v) => Poll::Ready(v.into_option()),
+            FfiPoll::Pending => Poll::Pending,
+            FfiPoll::Panicked => Poll::Ready(Some(RResult::RErr(RpcError::new(
+                ErrorKind::Panic,
+                "the stream panicked",
+            )))),
+        }
+    }
+}
+
+impl fmt::Debug for FfiStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FfiStream")
+    }
+}
+
+impl Drop for FfiStream {
+    fn drop(&mut self) {
+        // SAFETY: `data` is not used after this.
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+/// The stream returned by the method declared as `-> Streaming<T>` in `plugin_api_trait`,
+/// or passed to the method declared with the `Streaming<T>` argument.
+///
+/// The sender creates it from any stream with [`Streaming::new`], and the receiver polls
+/// the items across the boundary lazily. Each item is encoded by the codec of the interface.
+///
+/// The stream is polled by the executor of the receiver, and the stream returned by a library
+/// delays the drain of `Registry::reload` until it is dropped, same as `Bytes`.
+/// Dropping the returned stream cancels the call, see `CallContext::cancel`.
+/// The stream passed as an argument should not outlive the call, since the caller may be replaced
+/// by `Registry::reload` after it.
+pub struct Streaming<T> {
+    inner: Inner<T>,
+}
+
+enum Inner<T> {
+    Local(Pin<Box<dyn Stream<Item = T> + Send>>),
+    Remote {
+        /// Dropped before the library.
+        stream: FfiStream,
+        codec: Codec,
+        done: bool,
+        /// Cancel the call which returned the stream.
+        _cancel: Option<DropGuard>,
+        _library: Option<PluginGuard>,
+        _marker: PhantomData<fn() -> T>,
+    },
+}
+
+impl<T> Streaming<T> {
+    pub fn new<S: Stream<Item = T> + Send + 'static>(stream: S) -> Self {
+        Self {
+            inner: Inner::Local(Box::pin(stream)),
+        }
+    }
+
+    /// Wrap the stream replied by the library, the call is cancelled by the guard when it is dropped.
+    #[doc(hidden)]
+    pub fn from_library(
+        stream: FfiStream,
+        codec: Codec,
+        library: PluginGuard,
+        cancel: DropGuard,
+    ) -> Self {
+        Self::from_ffi(stream, codec, Some(library), Some(cancel))
+    }
+
+    pub(crate) fn from_ffi(
+        stream: FfiStream,
+        codec: Codec,
+        library: Option<PluginGuard>,
+        cancel: Option<DropGuard>,
+    ) -> Self {
+        Self {
+            inner: Inner::Remote {
+                stream,
+                codec,
+                done: false,
+                _cancel: cancel,
+                _library: library,
+                _marker: PhantomData,
+            },
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Send + 'static> Streaming<T> {
+    /// Get the next item, `None` if the stream ends. Same as `StreamExt::next`.
+    pub async fn next(&mut self) -> Option<Result<T, Error>> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Poll the next item encoded by the codec, the stream of another library is forwarded.
+    fn poll_reply(&mut self, cx: &mut Context<'_>, codec: Codec) -> Poll<Option<Reply>> {
+        match &mut self.inner {
+            Inner::Local(stream) => stream.as_mut().poll_next(cx).map(|x| {
+                x.map(|v| match codec.encode(&v) {
+                    Ok(v) => RResult::ROk(v.into()),
+                    Err(e) => RResult::RErr(RpcError::new(ErrorKind::Failed, e)),
+                })
+            }),
+            Inner::Remote {
+                stream,
+                codec: from,
+                done,
+                ..
+            } => {
+                if *done {
+                    return Poll::Ready(None);
+                }
+                let ret = match stream.poll_next(cx) {
+                    Poll::Ready(Some(RResult::ROk(v))) if *from != codec => Poll::Ready(Some(
+                        from.decode::<T>(&v)
+                            .and_then(|v| codec.encode(&v))
+                            .map(RVec::from)
+                            .map_err(|e| RpcError::new(ErrorKind::Failed, e))
+                            .into(),
+                    )),
+                    // Copy the buffers allocated by another library.
+                    Poll::Ready(Some(RResult::ROk(v))) => {
+                        Poll::Ready(Some(RResult::ROk(v.to_vec().into())))
+                    }
+                    Poll::Ready(Some(RResult::RErr(e))) => {
+                        Poll::Ready(Some(RResult::RErr(e.clone())))
+                    }
+                    ret => ret,
+                };
+                *done = matches!(ret, Poll::Ready(None));
+                ret
+            }
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Send + 'static> Stream for Streaming<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match &mut self.inner {
+            Inner::Local(stream) => stream.as_mut().poll_next(cx).map(|x| x.map(Ok)),
+            Inner::Remote {
+                stream,
+                codec,
+                done,
+                ..
+            } => {
+                if *done {
+                    return Poll::Ready(None);
+                }
+                let ret = match stream.poll_next(cx) {
+                    Poll::Ready(Some(RResult::ROk(v))) => {
+                        Some(codec.decode(&v).map_err(Error::Deserialize))
+                    }
+                    Poll::Ready(Some(RResult::RErr(e))) => Some(Err(e.into())),
+                    Poll::Ready(None) => None,
+                    Poll::Pending => return Poll::Pending,
+                };
+                *done = ret.is_none();
+                Poll::Ready(ret)
+            }
+        }
+    }
+}
+
+/// Create the stream from the items, e.g, `(0..10).collect::<Streaming<_>>()`.
+impl<T: Send + 'static> FromIterator<T> for Streaming<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        struct Iter<T>(std::vec::IntoIter<T>);
+
+        impl<T> Stream for Iter<T> {
+            type Item = T;
+
+            fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<T>> {
+                Poll::Ready(self.0.next())
+            }
+        }
+
+        // `Unpin` since the items are never pinned.
+        impl<T> Unpin for Iter<T> {}
+
+        Self::new(Iter(iter.into_iter().collect::<Vec<_>>().into_iter()))
+    }
+}
+
+impl<T> fmt::Debug for Streaming<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.inner {
+            Inner::Local(_) => "Streaming::Local",
+            Inner::Remote { .. } => "Streaming::Remote",
+        })
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Condvar, LazyLock, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use abi_stable::{sabi_extern_fn, std_types::RDuration};
+use async_ffi::{FfiFuture, FutureExt};
+
+use crate::registry::Registry;
+
+/// The wakers of the pending `Sleep` futures, which are woken by a background thread.
+///
+/// The thread exits when no future is pending, and is spawned again by the next one.
+struct Timer {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    running: bool,
+    wakers: BTreeMap<(Instant, u64), Waker>,
+}
+
+static TIMER: LazyLock<Timer> = LazyLock::new(|| Timer {
+    state: Mutex::default(),
+    cond: Condvar::new(),
+});
+
+impl Timer {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(&self) {
+        let mut state = self.state();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while let Some(entry) = state.wakers.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                drop(state);
+                expired.into_iter().for_each(Waker::wake);
+                state = self.state();
+                continue;
+            }
+            state = match state.wakers.first_key_value() {
+                Some(((deadline, _), _)) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    self.cond
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => {
+                    state.running = false;
+                    return;
+                }
+            };
+        }
+    }
+}
+
+/// Future which is ready after the deadline.
+struct Sleep {
+    deadline: Instant,
+    /// Registered in the timer with the waker.
+    id: Option<u64>,
+}
+
+impl Sleep {
+    fn new(duration: Duration) -> Self {
+        Self {
+            // Never expire if it overflows.
+            deadline: Instant::now()
+                .checked_add(duration)
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(u32::MAX as u64)),
+            id: None,
+        }
+    }
+}
+
+/// Sleep with the timer of this library, which is passed to the plugins by `Registry`.
+#[sabi_extern_fn]
+pub(crate) fn sleep(duration: RDuration) -> FfiFuture<()> {
+    Sleep::new(duration.into()).into_ffi()
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let deadline = self.deadline;
+        let mut state = TIMER.state();
+        if Instant::now() >= deadline {
+            if let Some(id) = self.id.take() {
+                state.wakers.remove(&(deadline, id));
+            }
+            return Poll::Ready(());
+        }
+        let id = *self.id.get_or_insert_with(|| {
+            state.next_id += 1;
+            state.next_id
+        });
+        state.wakers.insert((deadline, id), cx.waker().clone());
+        if !state.running {
+            let spawned = thread::Builder::new()
+                .name("ffi_rpc-timer".to_string())
+                .spawn(|| TIMER.run());
+            if spawned.is_err() {
+                // Expire now instead of never, so the call fails with `Error::Timeout`.
+                state.wakers.remove(&(deadline, id));
+                self.id = None;
+                return Poll::Ready(());
+            }
+            state.running = true;
+        } else if state.wakers.first_key_value().map(|(x, _)| x.1) == Some(id) {
+            // Wake the timer thread to wait for the earlier deadline.
+            TIMER.cond.notify_one();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = TIMER.state();
+            let waker = state.wakers.remove(&(self.deadline, id));
+            if state.wakers.is_empty() {
+                // Let the timer thread exit as soon as possible.
+                TIMER.cond.notify_one();
+            }
+            drop(state);
+            drop(waker);
+        }
+    }
+}
+
+/// Future returned by [`timeout`].
+pub struct Timeout<F> {
+    fut: F,
+    sleep: FfiFuture<()>,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Option<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is never moved out of the pinned `Timeout`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        if let Poll::Ready(v) = fut.poll(cx) {
+            return Poll::Ready(Some(v));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| None)
+    }
+}
+
+/// Run the future for at most `duration`, `None` if it is not finished in time.
+///
+/// The timer is driven by a background thread, thus it works in any async runtime.
+/// The thread runs in the library creating the registry, normally the host,
+/// so the plugins calling this never run it themselves.
+/// If the thread can not be spawned, the future times out immediately.
+pub fn timeout<F: Future>(reg: &Registry, duration: Duration, fut: F) -> Timeout<F> {
+    Timeout {
+        fut,
+        sleep: reg.sleep(duration),
+    }
+}
@@ -0,0 +1,91 @@
+//! The arguments are always encoded as an array, whatever the count is.
+extern crate self as args;
+
+mod common;
+
+use common::*;
+use ffi_rpc::abi_stable::std_types::RString;
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api(Args)]
+    pub trait ArgsApi {
+        async fn zero() -> i32;
+        async fn one(a: i32) -> i32;
+        async fn many(a: i32, b: String, c: Vec<i32>) -> i32;
+    }
+}
+
+mock!(Imp: args::api::ArgsApi);
+
+#[plugin_impl_trait]
+impl args::api::ArgsApi for Imp {
+    async fn zero(&self, _: &Registry) -> i32 {
+        0
+    }
+
+    async fn one(&self, _: &Registry, a: i32) -> i32 {
+        a
+    }
+
+    async fn many(&self, _: &Registry, a: i32, b: String, c: Vec<i32>) -> i32 {
+        a + b.len() as i32 + c.iter().sum::<i32>()
+    }
+}
+
+fn registry() -> Registry {
+    let r = Registry::default();
+    Imp::register_mock(&r, "args").unwrap();
+    r
+}
+
+/// Call the method with the raw MessagePack bytes like a non-Rust caller.
+async fn call_raw(r: &Registry, method: &str, param: Vec<u8>) -> Result<i32, ffi_rpc::Error> {
+    let ret = r.get("args").unwrap().api().call()(
+        RString::from(format!("args::api::ArgsApi::{method}")),
+        r,
+        param.into(),
+    )
+    .await
+    .into_result()?;
+    Ok(rmp_serde::from_slice(&ret).unwrap())
+}
+
+#[tokio::test]
+async fn zero_argument() {
+    let r = registry();
+    let api: api::Args = r.get_as("args").unwrap();
+    assert_eq!(api.try_zero(&r).await.unwrap(), 0);
+
+    let empty: [i32; 0] = [];
+    let ret = call_raw(&r, "zero", rmp_serde::to_vec(&empty).unwrap()).await;
+    assert_eq!(ret.unwrap(), 0);
+    let ret = call_raw(&r, "zero", rmp_serde::to_vec(&()).unwrap()).await;
+    assert!(matches!(ret, Err(ffi_rpc::Error::InvalidParams(_))));
+}
+
+#[tokio::test]
+async fn one_argument() {
+    let r = registry();
+    let api: api::Args = r.get_as("args").unwrap();
+    assert_eq!(api.try_one(&r, &1).await.unwrap(), 1);
+
+    let ret = call_raw(&r, "one", rmp_serde::to_vec(&[1]).unwrap()).await;
+    assert_eq!(ret.unwrap(), 1);
+    let ret = call_raw(&r, "one", rmp_serde::to_vec(&1).unwrap()).await;
+    assert!(matches!(ret, Err(ffi_rpc::Error::InvalidParams(_))));
+}
+
+#[tokio::test]
+async fn many_arguments() {
+    let r = registry();
+    let api: api::Args = r.get_as("args").unwrap();
+    assert_eq!(api.try_many(&r, &1, "ab", &vec![3, 4]).await.unwrap(), 10);
+
+    let param = rmp_serde::to_vec(&(1, "ab", [3, 4])).unwrap();
+    assert_eq!(call_raw(&r, "many", param).await.unwrap(), 10);
+    let param = rmp_serde::to_vec(&(1, "ab")).unwrap();
+    let ret = call_raw(&r, "many", param).await;
+    assert!(matches!(ret, Err(ffi_rpc::Error::InvalidParams(_))));
+}
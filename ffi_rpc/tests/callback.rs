@@ -0,0 +1,74 @@
+//! The callbacks invoked by the plugin.
+extern crate self as callback;
+
+mod common;
+
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Arc,
+};
+
+use common::*;
+use ffi_rpc::{callback::Callback, Error};
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api(Callbacks)]
+    pub trait CallbacksApi {
+        async fn map(n: i32, f: Callback<i32, i32>) -> Vec<i32>;
+        async fn panicked(f: Callback<i32, i32>) -> Option<String>;
+    }
+}
+
+mock!(Imp: callback::api::CallbacksApi);
+
+#[plugin_impl_trait]
+impl callback::api::CallbacksApi for Imp {
+    async fn map(&self, _: &Registry, n: i32, f: Callback<i32, i32>) -> Vec<i32> {
+        let mut ret = Vec::new();
+        for i in 0..n {
+            ret.push(f.call(i).await.unwrap());
+        }
+        ret
+    }
+
+    async fn panicked(&self, _: &Registry, f: Callback<i32, i32>) -> Option<String> {
+        match f.call(0).await {
+            Err(Error::Panic(msg)) => Some(msg),
+            _ => None,
+        }
+    }
+}
+
+fn registry() -> (Registry, api::Callbacks) {
+    let r = Registry::default();
+    Imp::register_mock(&r, "callbacks").unwrap();
+    let api = r.get_as("callbacks").unwrap();
+    (r, api)
+}
+
+#[tokio::test]
+async fn invoked_many_times() {
+    let (r, api) = registry();
+    let count = Arc::new(AtomicI32::new(0));
+    let f = Callback::new({
+        let count = count.clone();
+        move |x: i32| {
+            count.fetch_add(1, Ordering::Relaxed);
+            async move { x * 2 }
+        }
+    });
+    assert_eq!(api.try_map(&r, &3, f).await.unwrap(), [0, 2, 4]);
+    assert_eq!(count.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn panic_in_callback() {
+    let (r, api) = registry();
+    let f = Callback::new(|_: i32| async move { panic!("boom") });
+    assert_eq!(
+        api.try_panicked(&r, f).await.unwrap().as_deref(),
+        Some("boom")
+    );
+}
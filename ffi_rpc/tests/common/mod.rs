@@ -0,0 +1,72 @@
+//! The scaffold of the mock plugins shared by the tests.
+#![allow(dead_code, unused_imports, unused_macros)]
+
+// The generated code refers to these crates unqualified.
+pub use ffi_rpc::{
+    abi_stable, async_ffi, async_trait,
+    ffi_rpc_macro::{
+        self, plugin_impl_call, plugin_impl_instance, plugin_impl_mock, plugin_impl_requires,
+        plugin_impl_trait,
+    },
+    registry::Registry,
+    rmp_serde,
+};
+
+/// Define the mock plugin implementing the interface, at most one in a module.
+/// ```ignore
+/// mock!(Imp: crate_name::api::ClientApi);
+/// ```
+macro_rules! mock {
+    ($name:ident: $($interface:tt)::+) => {
+        #[plugin_impl_instance(|| $name)]
+        #[plugin_impl_call($($interface)::+)]
+        #[plugin_impl_mock]
+        pub struct $name;
+    };
+}
+pub(crate) use mock;
+
+/// The interface of the ping mocks.
+pub mod ping {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api_struct]
+    pub struct Ping;
+
+    #[ffi_rpc_macro::plugin_api_trait(Ping)]
+    pub trait PingApi {
+        async fn ping() -> i32;
+    }
+}
+
+/// Define the ping mock `$name::Imp` returning 1 in its own module, requiring the plugin ids.
+/// ```ignore
+/// ping_mock!(crate_name: a, "b", "c");
+/// ```
+macro_rules! ping_mock {
+    ($krate:ident: $name:ident $(, $dep:tt)*) => {
+        // Not all the mocks are used in the same way.
+        #[allow(dead_code)]
+        mod $name {
+            use super::*;
+
+            #[plugin_impl_instance(|| Imp)]
+            #[plugin_impl_call($krate::common::ping::PingApi)]
+            #[plugin_impl_requires($($dep),*)]
+            #[plugin_impl_mock]
+            pub struct Imp;
+
+            #[plugin_impl_trait]
+            impl $krate::common::ping::PingApi for Imp {
+                async fn ping(&self, _: &Registry) -> i32 {
+                    1
+                }
+            }
+
+            pub fn plugin() -> ffi_rpc::plugin::Plugin {
+                ffi_rpc::plugin::Plugin::from(*Imp::_FFI_API)
+            }
+        }
+    };
+}
+pub(crate) use ping_mock;
@@ -0,0 +1,201 @@
+//! The cancellation, the timeout and the metadata of the calls.
+extern crate self as context;
+
+mod common;
+
+use std::{
+    future::{pending, Future},
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::{Context, Wake, Waker},
+    time::Duration,
+};
+
+use common::*;
+use ffi_rpc::{
+    context::{CallContext, CancelToken},
+    stream::Streaming,
+    Error,
+};
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api(Wait)]
+    pub trait WaitApi {
+        async fn forever() -> i32;
+        async fn now() -> i32;
+        async fn count() -> Streaming<i32>;
+        async fn never() -> i32;
+    }
+
+    #[ffi_rpc_macro::plugin_api(Outer)]
+    pub trait OuterApi {
+        async fn relay() -> Option<String>;
+    }
+
+    #[ffi_rpc_macro::plugin_api(Inner)]
+    pub trait InnerApi {
+        async fn request_id() -> Option<String>;
+    }
+}
+
+/// The tokens of the last calls, taken by the implementation.
+static FOREVER: Mutex<Option<CancelToken>> = Mutex::new(None);
+static NOW: Mutex<Option<CancelToken>> = Mutex::new(None);
+static COUNT: Mutex<Option<CancelToken>> = Mutex::new(None);
+
+mock!(Imp: context::api::WaitApi);
+
+#[plugin_impl_trait]
+impl context::api::WaitApi for Imp {
+    async fn forever(&self, _: &Registry) -> i32 {
+        *FOREVER.lock().unwrap() = Some(CallContext::current().cancel);
+        pending().await
+    }
+
+    async fn now(&self, _: &Registry) -> i32 {
+        *NOW.lock().unwrap() = Some(CallContext::current().cancel);
+        1
+    }
+
+    async fn count(&self, _: &Registry) -> Streaming<i32> {
+        *COUNT.lock().unwrap() = Some(CallContext::current().cancel);
+        (0..3).collect()
+    }
+
+    async fn never(&self, _: &Registry) -> i32 {
+        pending().await
+    }
+}
+
+/// Call `inner` from the plugin.
+mod outer {
+    use super::*;
+
+    mock!(Imp: context::api::OuterApi);
+
+    #[plugin_impl_trait]
+    impl context::api::OuterApi for Imp {
+        async fn relay(&self, r: &Registry) -> Option<String> {
+            let inner: api::Inner = r.get_as("inner").unwrap();
+            inner.try_request_id(r).await.unwrap()
+        }
+    }
+}
+
+mod inner {
+    use super::*;
+
+    mock!(Imp: context::api::InnerApi);
+
+    #[plugin_impl_trait]
+    impl context::api::InnerApi for Imp {
+        async fn request_id(&self, _: &Registry) -> Option<String> {
+            CallContext::current().get("request-id").map(String::from)
+        }
+    }
+}
+
+fn registry() -> (Registry, api::Wait) {
+    let r = Registry::default();
+    Imp::register_mock(&r, "wait").unwrap();
+    let api = r.get_as("wait").unwrap();
+    (r, api)
+}
+
+/// The waker whose clones are counted by the strong count.
+struct Counter;
+
+impl Wake for Counter {
+    fn wake(self: Arc<Self>) {}
+}
+
+#[test]
+fn child_cancelled_with_parent() {
+    let parent = CancelToken::new();
+    let child = parent.child();
+    let grandchild = child.child();
+    assert!(!child.is_cancelled());
+
+    parent.cancel();
+    assert!(child.is_cancelled());
+    assert!(grandchild.is_cancelled());
+    // Already cancelled when created.
+    assert!(parent.child().is_cancelled());
+}
+
+#[test]
+fn parent_not_cancelled_with_child() {
+    let parent = CancelToken::new();
+    let child = parent.child();
+    child.cancel();
+    assert!(!parent.is_cancelled());
+}
+
+#[test]
+fn cancelled_unregistered_on_drop() {
+    let token = CancelToken::new();
+    let counter = Arc::new(Counter);
+    let waker = Waker::from(counter.clone());
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut fut = pin!(token.cancelled());
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(Arc::strong_count(&counter), 3);
+    }
+    assert_eq!(Arc::strong_count(&counter), 2);
+}
+
+#[tokio::test]
+async fn dropped_call_cancelled() {
+    let (r, api) = registry();
+    {
+        let mut fut = pin!(api.try_forever(&r));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+        assert!(!FOREVER.lock().unwrap().as_ref().unwrap().is_cancelled());
+    }
+    assert!(FOREVER.lock().unwrap().take().unwrap().is_cancelled());
+}
+
+#[tokio::test]
+async fn finished_call_not_cancelled() {
+    let (r, api) = registry();
+    assert_eq!(api.try_now(&r).await.unwrap(), 1);
+    assert!(!NOW.lock().unwrap().take().unwrap().is_cancelled());
+}
+
+#[tokio::test]
+async fn dropped_stream_cancelled() {
+    let (r, api) = registry();
+    let mut s = api.try_count(&r).await.unwrap();
+    assert_eq!(s.next().await.unwrap().unwrap(), 0);
+    let token = COUNT.lock().unwrap().take().unwrap();
+    assert!(!token.is_cancelled());
+    drop(s);
+    assert!(token.is_cancelled());
+}
+
+#[tokio::test]
+async fn timeout() {
+    let (r, api) = registry();
+    let ret = api
+        .with_timeout(Duration::from_millis(10))
+        .try_never(&r)
+        .await;
+    assert!(matches!(ret, Err(Error::Timeout(func)) if func.ends_with("WaitApi::never")));
+}
+
+#[tokio::test]
+async fn metadata_forwarded() {
+    let r = Registry::default();
+    outer::Imp::register_mock(&r, "outer").unwrap();
+    inner::Imp::register_mock(&r, "inner").unwrap();
+    let api: api::Outer = r.get_as("outer").unwrap();
+    assert_eq!(api.try_relay(&r).await.unwrap(), None);
+
+    let ctx = CallContext::default().with_metadata("request-id", "42");
+    let ret = ctx.scope(api.try_relay(&r)).await.unwrap();
+    assert_eq!(ret.as_deref(), Some("42"));
+}
@@ -0,0 +1,65 @@
+//! The errors returned by the `try_*` methods.
+extern crate self as errors;
+
+mod common;
+
+use common::*;
+use ffi_rpc::{plugin::Interface, Error};
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api_struct]
+    pub struct Errors;
+
+    #[ffi_rpc_macro::plugin_api_trait(Errors)]
+    pub trait ErrorsApi {
+        async fn boom() -> i32;
+    }
+}
+
+mock!(Imp: errors::api::ErrorsApi);
+
+#[plugin_impl_trait]
+impl errors::api::ErrorsApi for Imp {
+    async fn boom(&self, _: &Registry) -> i32 {
+        panic!("boom")
+    }
+}
+
+#[tokio::test]
+async fn panic() {
+    let r = Registry::default();
+    Imp::register_mock(&r, "errors").unwrap();
+    let api: api::Errors = r.get_as("errors").unwrap();
+    let ret = api.try_boom(&r).await;
+    assert!(matches!(ret, Err(Error::Panic(msg)) if msg == "boom"));
+}
+
+#[test]
+fn incompatible() {
+    let r = Registry::default();
+    Imp::register_mock(&r, "errors").unwrap();
+    let api = r.get("errors").unwrap().api();
+    let expected = api::ERRORSAPI_INTERFACE;
+    assert!(api.check_interfaces(&[expected]).is_ok());
+
+    // The interface is changed by the host.
+    let changed = Interface {
+        fingerprint: expected.fingerprint ^ 1,
+        ..expected
+    };
+    let ret = api.check_interfaces(&[changed]);
+    assert!(matches!(
+        ret,
+        Err(Error::Incompatible { expected: x, found: Some(y), .. })
+            if x == changed.fingerprint && y == expected.fingerprint
+    ));
+
+    let missing = Interface {
+        path: "errors::api::MissingApi",
+        ..expected
+    };
+    let ret = api.check_interfaces(&[missing]);
+    assert!(matches!(ret, Err(Error::Incompatible { found: None, .. })));
+}
@@ -0,0 +1,69 @@
+//! The registration order of the plugins with dependencies.
+#![cfg(feature = "loader")]
+extern crate self as loader;
+
+mod common;
+
+use common::{ping::Ping, *};
+use ffi_rpc::{
+    loader::{register_all, PluginEntry},
+    plugin::Plugin,
+    Error,
+};
+
+ping_mock!(loader: a, "b");
+ping_mock!(loader: b, "a");
+ping_mock!(loader: c, "missing");
+ping_mock!(loader: d, "e");
+ping_mock!(loader: e);
+
+fn entry(id: &str) -> PluginEntry {
+    PluginEntry {
+        id: id.to_string(),
+        path: None,
+        config: Default::default(),
+    }
+}
+
+fn ids(r: &Registry) -> Vec<String> {
+    r.find_all::<Ping>().into_iter().map(|(id, _)| id).collect()
+}
+
+#[test]
+fn missing_dependency() {
+    let r = Registry::default();
+    let ret = c::Imp::register_mock(&r, "c");
+    assert!(matches!(ret, Err(Error::MissingDependency(_))));
+
+    let (c, e) = (entry("c"), entry("e"));
+    let errors = register_all(&r, vec![(&c, c::plugin()), (&e, e::plugin())]).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(
+        matches!(&errors[0], (id, Error::MissingDependency(dep)) if id == "c" && dep.contains("missing"))
+    );
+    assert_eq!(ids(&r), ["e"]);
+}
+
+#[test]
+fn cyclic_dependency() {
+    let r = Registry::default();
+    let entries: Vec<_> = ["a", "b", "e"].map(entry).into();
+    let plugins: Vec<Plugin> = vec![a::plugin(), b::plugin(), e::plugin()];
+    let errors = register_all(&r, entries.iter().zip(plugins).collect()).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    for (id, e) in errors {
+        assert!(
+            matches!(e, Error::CyclicDependency(ids) if ids == ["a", "b"]),
+            "{id}"
+        );
+    }
+    assert_eq!(ids(&r), ["e"]);
+}
+
+#[test]
+fn dependency_first() {
+    let r = Registry::default();
+    let (d, e) = (entry("d"), entry("e"));
+    register_all(&r, vec![(&d, d::plugin()), (&e, e::plugin())]).unwrap();
+    assert_eq!(ids(&r), ["e", "d"]);
+}
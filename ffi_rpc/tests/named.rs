@@ -0,0 +1,56 @@
+//! The named arguments with defaults, called by the host compiled with an older interface.
+extern crate self as named;
+
+mod common;
+
+use common::*;
+use ffi_rpc::{abi_stable::std_types::RString, codec::Codec, serde::Serialize};
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api_struct]
+    pub struct Named;
+
+    #[ffi_rpc_macro::plugin_api_trait(Named, named = true)]
+    pub trait NamedApi {
+        async fn add(a: i32, #[ffi_rpc(default)] b: i32) -> i32;
+    }
+}
+
+mock!(Imp: named::api::NamedApi);
+
+#[plugin_impl_trait]
+impl named::api::NamedApi for Imp {
+    async fn add(&self, _: &Registry, a: i32, b: i32) -> i32 {
+        a + b
+    }
+}
+
+/// The arguments of `add` before `b` is added.
+#[derive(Serialize)]
+#[serde(crate = "ffi_rpc::serde")]
+struct OldParams {
+    a: i32,
+}
+
+#[tokio::test]
+async fn default_omitted() {
+    let r = Registry::default();
+    Imp::register_mock(&r, "named").unwrap();
+    let api: api::Named = r.get_as("named").unwrap();
+    assert_eq!(api.try_add(&r, &1, &2).await.unwrap(), 3);
+
+    let param = Codec::MessagePack
+        .encode_named(&OldParams { a: 1 })
+        .unwrap();
+    let ret = r.get("named").unwrap().api().call()(
+        RString::from("named::api::NamedApi::add"),
+        &r,
+        param.into(),
+    )
+    .await
+    .into_result()
+    .unwrap();
+    assert_eq!(Codec::MessagePack.decode::<i32>(&ret).unwrap(), 1);
+}
@@ -0,0 +1,20 @@
+//! Registration of the mock plugins.
+extern crate self as registry;
+
+mod common;
+
+use common::{ping::Ping, *};
+use ffi_rpc::Error;
+
+ping_mock!(registry: a);
+
+#[tokio::test]
+async fn duplicate_id() {
+    let r = Registry::default();
+    a::Imp::register_mock(&r, "ping").unwrap();
+    let ret = a::Imp::register_mock(&r, "ping");
+    assert!(matches!(ret, Err(Error::AlreadyRegistered(id)) if id == "ping"));
+
+    let api: Ping = r.get_as("ping").unwrap();
+    assert_eq!(api.try_ping(&r).await.unwrap(), 1);
+}
@@ -0,0 +1,118 @@
+//! The streams returned by the plugin and passed to it.
+extern crate self as stream;
+
+mod common;
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use common::*;
+use ffi_rpc::{futures_core::Stream, stream::Streaming, Error};
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api(Streams)]
+    pub trait StreamsApi {
+        async fn count(n: i32) -> Streaming<i32>;
+        async fn boom() -> Streaming<i32>;
+        async fn sum(xs: Streaming<i32>) -> i32;
+        async fn first(xs: Streaming<i32>) -> Option<i32>;
+    }
+}
+
+mock!(Imp: stream::api::StreamsApi);
+
+#[plugin_impl_trait]
+impl stream::api::StreamsApi for Imp {
+    async fn count(&self, _: &Registry, n: i32) -> Streaming<i32> {
+        (0..n).collect()
+    }
+
+    async fn boom(&self, _: &Registry) -> Streaming<i32> {
+        Streaming::new(Boom(0))
+    }
+
+    async fn sum(&self, _: &Registry, mut xs: Streaming<i32>) -> i32 {
+        let mut ret = 0;
+        while let Some(x) = xs.next().await {
+            ret += x.unwrap();
+        }
+        ret
+    }
+
+    async fn first(&self, _: &Registry, mut xs: Streaming<i32>) -> Option<i32> {
+        xs.next().await.map(Result::unwrap)
+    }
+}
+
+/// Yield 0 and 1, then panic.
+struct Boom(i32);
+
+impl Stream for Boom {
+    type Item = i32;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<i32>> {
+        if self.0 == 2 {
+            panic!("boom");
+        }
+        self.0 += 1;
+        Poll::Ready(Some(self.0 - 1))
+    }
+}
+
+/// Yield 0, 1, 2, ... forever.
+struct Naturals(i32);
+
+impl Stream for Naturals {
+    type Item = i32;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<i32>> {
+        self.0 += 1;
+        Poll::Ready(Some(self.0 - 1))
+    }
+}
+
+fn registry() -> (Registry, api::Streams) {
+    let r = Registry::default();
+    Imp::register_mock(&r, "streams").unwrap();
+    let api = r.get_as("streams").unwrap();
+    (r, api)
+}
+
+#[tokio::test]
+async fn all_items() {
+    let (r, api) = registry();
+    let mut s = api.try_count(&r, &3).await.unwrap();
+    for i in 0..3 {
+        assert_eq!(s.next().await.unwrap().unwrap(), i);
+    }
+    assert!(s.next().await.is_none());
+    assert!(s.next().await.is_none());
+}
+
+#[tokio::test]
+async fn panic_in_stream() {
+    let (r, api) = registry();
+    let mut s = api.try_boom(&r).await.unwrap();
+    assert_eq!(s.next().await.unwrap().unwrap(), 0);
+    assert_eq!(s.next().await.unwrap().unwrap(), 1);
+    let ret = s.next().await.unwrap();
+    assert!(matches!(ret, Err(Error::Panic(msg)) if msg == "boom"));
+    assert!(s.next().await.is_none());
+}
+
+#[tokio::test]
+async fn stream_argument() {
+    let (r, api) = registry();
+    let xs: Streaming<i32> = (1..=4).collect();
+    assert_eq!(api.try_sum(&r, xs).await.unwrap(), 10);
+    let xs: Streaming<i32> = [].into_iter().collect();
+    assert_eq!(api.try_sum(&r, xs).await.unwrap(), 0);
+
+    // Only the items polled by the plugin are produced.
+    let xs = Streaming::new(Naturals(0));
+    assert_eq!(api.try_first(&r, xs).await.unwrap(), Some(0));
+}
@@ -0,0 +1,51 @@
+//! The sync methods called without any async runtime.
+extern crate self as sync;
+
+mod common;
+
+use common::*;
+use ffi_rpc::Error;
+
+mod api {
+    use super::*;
+
+    #[ffi_rpc_macro::plugin_api(Sync)]
+    pub trait SyncApi {
+        fn add(a: i32, b: i32) -> i32;
+        fn boom() -> i32;
+    }
+}
+
+mock!(Imp: sync::api::SyncApi);
+
+#[plugin_impl_trait]
+impl sync::api::SyncApi for Imp {
+    fn add(&self, _: &Registry, a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    fn boom(&self, _: &Registry) -> i32 {
+        panic!("boom")
+    }
+}
+
+fn registry() -> (Registry, api::Sync) {
+    let r = Registry::default();
+    Imp::register_mock(&r, "sync").unwrap();
+    let api = r.get_as("sync").unwrap();
+    (r, api)
+}
+
+#[test]
+fn without_runtime() {
+    let (r, api) = registry();
+    assert_eq!(api.try_add(&r, &1, &2).unwrap(), 3);
+    assert_eq!(api.add(&r, &1, &2), 3);
+}
+
+#[test]
+fn panic() {
+    let (r, api) = registry();
+    let ret = api.try_boom(&r);
+    assert!(matches!(ret, Err(Error::Panic(msg)) if msg == "boom"));
+}
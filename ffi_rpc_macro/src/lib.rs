@@ -1,14 +1,17 @@
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
     parse::{Parse, ParseStream, Parser},
     parse_macro_input, parse_quote,
     punctuated::Punctuated,
-    Expr, ExprClosure, Fields, FnArg, Ident, ImplItem, ItemImpl, ItemStruct, ItemTrait, Pat, Path,
-    Token, TraitItem, TraitItemFn, Type,
+    Expr, ExprClosure, ExprLit, Fields, FnArg, GenericArgument, Ident, ImplItem, ItemFn, ItemImpl,
+    ItemStruct, ItemTrait, Lit, Meta, MetaNameValue, Pat, Path, PathArguments, ReturnType,
+    Signature, Token, TraitItem, TraitItemFn, Type,
 };
 
 /// Expand to `plugin_api_struct` + `plugin_api_trait`
+///
+/// The options of `plugin_api_struct` and `plugin_api_trait` can be passed after the struct name.
 /// ```ignore
 /// #[plugin_api(Client)]
 /// pub trait ClientApi {
@@ -17,7 +20,7 @@ use syn::{
 /// ```
 /// is equal to
 /// ```ignore
-/// #[plugin_api_struct]
+/// #[plugin_api_struct(ClientApi)]
 /// pub struct Client;  // visibility is the same as the trait.
 ///
 /// #[plugin_api_trait(Client)]
@@ -27,15 +30,24 @@ use syn::{
 /// ```
 #[proc_macro_attribute]
 pub fn plugin_api(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let struct_name = parse_macro_input!(attr as Ident);
+    let attr = parse_macro_input!(attr as Args);
     let input = parse_macro_input!(item as ItemTrait);
     let vis = &input.vis;
 
+    let [struct_name] = attr.paths.as_slice() else {
+        panic!("expected the name of API struct");
+    };
+    let trait_name = &input.ident;
+    let (trait_options, struct_options): (Vec<_>, Vec<_>) = attr
+        .options
+        .iter()
+        .partition(|x| x.path.is_ident("codec") || x.path.is_ident("named"));
+
     let expanded = quote! {
-        #[ffi_rpc_macro::plugin_api_struct]
+        #[ffi_rpc_macro::plugin_api_struct(#trait_name #(, #struct_options)*)]
         #vis struct #struct_name;
 
-        #[ffi_rpc_macro::plugin_api_trait(#struct_name)]
+        #[ffi_rpc_macro::plugin_api_trait(#struct_name #(, #trait_options)*)]
         #input
     };
 
@@ -45,10 +57,16 @@ pub fn plugin_api(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// Define several useful functions for API struct.
 ///
 /// Note that the struct field should be named field and implement `Default`.
+///
+/// The implemented traits can be passed, seperated by a comma.
+/// `new` will check their fingerprints and return `Error::Incompatible` on mismatch,
+/// and `Registry::find` will look up the plugins implementing them.
+///
+/// The default id in the registry can be declared with `id = "..."`, which is used by `Registry::get_default`.
 /// ```ignore
 /// use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
 ///
-/// #[plugin_api_struct]
+/// #[plugin_api_struct(ClientApi1, ClientApi2, id = "client")]
 /// pub struct Client {
 ///     field: i32,
 /// }
@@ -58,17 +76,34 @@ pub fn plugin_api(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// //     no_default: CustomType,
 /// // }
 ///
-/// let mut r = Registry::default();
+/// let r = Registry::default();
 /// let lib = Client::new(
 ///     format!("./target/debug/{}client{}", DLL_PREFIX, DLL_SUFFIX).as_ref(),
-///     &mut r,
+///     &r,
+///     "client",
+/// ).unwrap();
+/// // or pass the config to the plugin instance and `on_load` hook.
+/// let lib = Client::new_with_config(
+///     format!("./target/debug/{}client{}", DLL_PREFIX, DLL_SUFFIX).as_ref(),
+///     &r,
 ///     "client",
+///     &Config { .. },
 /// ).unwrap();
 /// let client = Client::from(r.get("client").unwrap());
+/// // or check the interfaces and return `Error` instead of panicking.
+/// let client = r.get_as::<Client>("client").unwrap();
+/// // or use the default id.
+/// let client = r.get_default::<Client>().unwrap();
+/// // or find any plugin implementing `ClientApi1` and `ClientApi2`.
+/// let client: Client = r.find().unwrap();
+/// // fail the calls with `Error::Timeout` after 2 seconds.
+/// let ret = client.with_timeout(Duration::from_secs(2)).try_add(&r, &1, &2).await;
 /// ```
 #[proc_macro_attribute]
-pub fn plugin_api_struct(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn plugin_api_struct(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = parse_macro_input!(attr as Args);
     let mut input = parse_macro_input!(item as ItemStruct);
+    let interfaces: Vec<_> = attr.paths.iter().map(interface_const).collect();
     let ident = &input.ident;
     let vis = &input.vis;
     let fields: Vec<_> = if let Fields::Named(field) = &mut input.fields {
@@ -79,35 +114,75 @@ pub fn plugin_api_struct(_: TokenStream, item: TokenStream) -> TokenStream {
             .collect();
         field
             .named
-            .push(parse_quote!(_ffi_ref: ffi_rpc::plugin::PluginApiRef));
+            .push(parse_quote!(_ffi_ref: ffi_rpc::plugin::Plugin));
+        field
+            .named
+            .push(parse_quote!(_ffi_timeout: Option<std::time::Duration>));
         ret
     } else if let Fields::Unit = &input.fields {
-        input.fields = Fields::Named(parse_quote!({_ffi_ref: ffi_rpc::plugin::PluginApiRef}));
+        input.fields = Fields::Named(parse_quote!({
+            _ffi_ref: ffi_rpc::plugin::Plugin,
+            _ffi_timeout: Option<std::time::Duration>,
+        }));
         Vec::new()
     } else {
         panic!("Expected named fields in struct");
     };
 
+    let default_id = attr.option("id").map(|id| {
+        quote! {
+            impl ffi_rpc::plugin::DefaultId for #ident {
+                const ID: &'static str = #id;
+            }
+        }
+    });
+    if let Some(x) = attr.options.iter().find(|x| !x.path.is_ident("id")) {
+        panic!("unknown option `{}`", x.path.to_token_stream());
+    }
+
     let expanded = quote! {
         #input
 
         impl #ident {
             #vis fn new<S: Into<String>>(path: &std::path::Path,
-                reg: &mut ffi_rpc::registry::Registry,
-                id: S) -> Result<Self, abi_stable::library::LibraryError> {
-                let api = ffi_rpc::plugin::load_plugin(path)?;
-                reg.item.insert(id.into().into(), api);
+                reg: &ffi_rpc::registry::Registry,
+                id: S) -> Result<Self, ffi_rpc::Error> {
+                Self::new_with_config(path, reg, id, &())
+            }
+
+            #vis fn new_with_config<S: Into<String>, C: ffi_rpc::serde::Serialize + ?Sized>(path: &std::path::Path,
+                reg: &ffi_rpc::registry::Registry,
+                id: S,
+                config: &C) -> Result<Self, ffi_rpc::Error> {
+                let api = ffi_rpc::plugin::load_plugin(path, <Self as ffi_rpc::plugin::ApiStruct>::INTERFACES)?;
+                reg.register(id, api.clone(), config)?;
                 Ok(Self{
                     _ffi_ref: api,
+                    _ffi_timeout: None,
                     #(#fields: Default::default()),*
                 })
             }
+
+            /// Fail the calls with `Error::Timeout` if they are not finished in `timeout`,
+            /// the deadline is also passed to the plugin by `CallContext`.
+            /// The sync methods block until the reply, so they only get the deadline and never time out.
+            #vis fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+                self._ffi_timeout = Some(timeout);
+                self
+            }
         }
 
-        impl From<ffi_rpc::plugin::PluginApiRef> for #ident {
-            fn from(v: ffi_rpc::plugin::PluginApiRef) -> Self {
+        impl ffi_rpc::plugin::ApiStruct for #ident {
+            const INTERFACES: &'static [ffi_rpc::plugin::Interface] = &[#(#interfaces),*];
+        }
+
+        #default_id
+
+        impl From<ffi_rpc::plugin::Plugin> for #ident {
+            fn from(v: ffi_rpc::plugin::Plugin) -> Self {
                 Self {
                     _ffi_ref: v,
+                    _ffi_timeout: None,
                     #(#fields: Default::default()),*
                 }
             }
@@ -119,27 +194,226 @@ pub fn plugin_api_struct(_: TokenStream, item: TokenStream) -> TokenStream {
 
 /// Define ffi call for each method in API struct.
 ///
+/// A constant named `"{trait_name.to_uppercase()}_INTERFACE"` is created to describe the interface,
+/// its fingerprint is computed from the method names, argument/return types and the codec.
+///
 /// Method arguments and return type should be:
 /// - no self (prepend automatically)
-/// - always async
+/// - async, or sync which is called by `PluginApi::invoke_sync` without the executor
 /// - serializable
 /// - no reference and mut
 ///
 /// The implemetation will always use value, while the caller will always use reference.
+///
+/// Except that `ffi_rpc::bytes::Bytes` and `&[u8]` skip the codec and are passed as the attachments of
+/// `Request`. `Bytes` is moved to the implementation without copying, and `&[u8]` is lent during the call.
+/// Returning `Bytes` moves the buffer back to the caller.
+///
+/// If the method returns `ffi_rpc::stream::Streaming<T>` (or `Result<Streaming<T>, E>`),
+/// the caller gets the stream polled across the boundary by `PluginApi::invoke_stream`,
+/// and each item is encoded by the codec.
+/// A `Streaming<T>` argument is also passed as an attachment, which is pulled lazily by the implementation.
+///
+/// A `ffi_rpc::callback::Callback<A, R>` argument is created by the caller from an async closure,
+/// and the implementation can invoke it any number of times during the call.
+///
+/// Dropping the future of the method cancels `CallContext::cancel` of the call,
+/// see `ffi_rpc::context::CallContext::current`.
+/// The timeout set by `with_timeout` of the API struct fails the call with `Error::Timeout`,
+/// which also cancels it, and the deadline is passed as `CallContext::deadline`.
+/// The context of the current call is forwarded, see `ffi_rpc::context::CallContext::scope`.
+/// For the methods returning `Streaming`, the timeout only covers the call but not the items.
+/// The sync methods are never timed out, they only get the deadline.
+///
+/// A `try_{method}` variant is generated next to each method, which returns `ffi_rpc::Error`
+/// instead of panicking on codec mismatches, unknown functions and library panics.
+///
+/// If the method returns `Result<T, E>`, the `Err` branch is transferred as a domain error,
+/// so `try_{method}` returns `Result<Result<T, E>, ffi_rpc::Error>`.
+///
+/// The arguments and return values are serialized by MessagePack by default, which can be changed by
+/// `codec = "json" | "postcard" | "bincode" | "cbor"` with the feature of the same name enabled,
+/// otherwise the interface fails to compile.
+/// The codec is recorded in the interface, so the host and the plugin always agree on it.
+///
+/// The arguments are always encoded as an array in the declaration order, whatever the count is:
+/// `[]`, `[a]`, `[a, b]`, where the attachments are replaced by their indexes. The return value is encoded as is.
+///
+/// With `named = true`, the arguments are encoded as a map by their names instead of a tuple,
+/// which requires a self-describing codec (not `postcard` or `bincode`).
+/// Then the arguments can be added, removed or reordered, thus only the method names and return types
+/// are included in the fingerprint. The arguments added later should be marked with `#[ffi_rpc(default)]`,
+/// so they are `Default::default()` if the host compiled with the older interface omits them.
 /// ```ignore
 /// pub struct Client;
 ///
-/// #[plugin_api_trait(Client)]
+/// #[plugin_api_trait(Client)]     // or #[plugin_api_trait(Client, codec = "json")]
 /// pub trait ClientApi {
 ///     async fn add1(a: i32, b: i32) -> i32;
 /// }
+///
+/// // Generated:
+/// // pub async fn add1(&self, _ffi_reg: &Registry, a: &i32, b: &i32) -> i32;
+/// // pub async fn try_add1(&self, _ffi_reg: &Registry, a: &i32, b: &i32) -> Result<i32, ffi_rpc::Error>;
+/// ```
+///
+/// ```ignore
+/// #[plugin_api_trait(Client, named = true)]
+/// pub trait ClientApi {
+///     // `c` is added later, the older host still works.
+///     async fn add1(a: i32, b: i32, #[ffi_rpc(default)] c: i32) -> i32;
+/// }
 /// ```
 #[proc_macro_attribute]
 pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let struct_name = parse_macro_input!(attr as Ident);
+    let attr = parse_macro_input!(attr as Args);
     let mut input = parse_macro_input!(item as ItemTrait);
-    let trait_name = &input.ident;
-    let vis = &input.vis;
+    let [struct_name] = attr.paths.as_slice() else {
+        panic!("expected the name of API struct");
+    };
+    if let Some(x) = attr
+        .options
+        .iter()
+        .find(|x| !x.path.is_ident("codec") && !x.path.is_ident("named"))
+    {
+        panic!("unknown option `{}`", x.path.to_token_stream());
+    }
+    let named = match attr.option("named") {
+        Some(Expr::Lit(ExprLit {
+            lit: Lit::Bool(x), ..
+        })) => x.value,
+        Some(_) => panic!("expected `named = true` or `named = false`"),
+        None => false,
+    };
+    let codec_name = match attr.option("codec") {
+        Some(Expr::Lit(ExprLit {
+            lit: Lit::Str(x), ..
+        })) => x.value(),
+        Some(_) => panic!("expected codec name"),
+        None => "msgpack".to_string(),
+    };
+    let codec = match codec_name.as_str() {
+        "msgpack" => quote!(ffi_rpc::codec::Codec::MessagePack),
+        "json" => quote!(ffi_rpc::codec::Codec::Json),
+        "postcard" => quote!(ffi_rpc::codec::Codec::Postcard),
+        "bincode" => quote!(ffi_rpc::codec::Codec::Bincode),
+        "cbor" => quote!(ffi_rpc::codec::Codec::Cbor),
+        x => panic!("unknown codec `{x}`"),
+    };
+    // The interface refers to the codec through the item which only exists with the feature,
+    // so it fails to compile instead of failing at runtime if the feature is disabled.
+    let enabled_codec = match codec_name.as_str() {
+        "msgpack" => codec.clone(),
+        x => {
+            let name = format_ident!("{}", x.to_uppercase());
+            quote!(ffi_rpc::codec::enabled::#name)
+        }
+    };
+    if named && matches!(codec_name.as_str(), "postcard" | "bincode") {
+        panic!("`named` requires a self-describing codec");
+    }
+    let trait_name = &input.ident.clone();
+    let vis = &input.vis.clone();
+    // The named arguments are decoded into these structs by the implementation.
+    let params: Vec<_> = input
+        .items
+        .iter_mut()
+        .filter_map(|item| {
+            let TraitItem::Fn(TraitItemFn { sig, .. }) = item else {
+                return None;
+            };
+            let default: Vec<_> = strip_defaults(sig, named)
+                .into_iter()
+                .map(|x| x.then(|| quote!(#[serde(default)])))
+                .collect();
+            let (param, ty): (Vec<_>, Vec<_>) = sig
+                .inputs
+                .iter()
+                .filter_map(|x| match x {
+                    FnArg::Typed(x) => Some((&x.pat, wire_type(&x.ty))),
+                    _ => None,
+                })
+                .unzip();
+            let ident = params_struct(trait_name, &sig.ident);
+            Some(quote! {
+                #[doc(hidden)]
+                #[allow(non_camel_case_types)]
+                #[derive(ffi_rpc::serde::Deserialize)]
+                #[serde(crate = "ffi_rpc::serde")]
+                #vis struct #ident {
+                    #(#default #param: #ty),*
+                }
+
+                impl #ident {
+                    #[allow(clippy::unused_unit)]
+                    #vis fn into_tuple(self) -> (#(#ty,)*) {
+                        (#(self.#param,)*)
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let interface = format_ident!("{}_INTERFACE", trait_name.to_string().to_uppercase());
+    let interface_path = format!("::{}", trait_name);
+    let method_info: Vec<_> = input
+        .items
+        .iter()
+        .filter_map(|item| {
+            if let TraitItem::Fn(TraitItemFn { sig, .. }) = item {
+                let name = format!("::{}::{}", trait_name, sig.ident);
+                let signature = signature(sig);
+                Some(quote! {
+                    ffi_rpc::plugin::Method {
+                        name: concat!(module_path!(), #name),
+                        signature: #signature,
+                    }
+                })
+            } else {
+                None
+            }
+        })
+        .collect();
+    let fingerprint = fingerprint(
+        &input
+            .items
+            .iter()
+            .filter_map(|item| {
+                if let TraitItem::Fn(TraitItemFn { sig, .. }) = item {
+                    // The arguments are matched by names in the named mode.
+                    let param: Vec<_> = if named {
+                        vec!["named".to_string()]
+                    } else {
+                        sig.inputs
+                            .iter()
+                            .map(|x| match x {
+                                FnArg::Typed(x) => x.ty.to_token_stream().to_string(),
+                                FnArg::Receiver(x) => x.to_token_stream().to_string(),
+                            })
+                            .collect()
+                    };
+                    let ret = match &sig.output {
+                        ReturnType::Default => "()".to_string(),
+                        ReturnType::Type(_, ty) => ty.to_token_stream().to_string(),
+                    };
+                    // The sync methods are called by another function.
+                    let sync = if sig.asyncness.is_none() { "sync " } else { "" };
+                    Some(format!(
+                        "{}{}({})->{}",
+                        sync,
+                        sig.ident,
+                        param.join(","),
+                        ret
+                    ))
+                } else {
+                    None
+                }
+            })
+            // Keep the fingerprint of the default codec unchanged.
+            .chain((codec_name != "msgpack").then(|| format!("codec={codec_name}")))
+            .collect::<Vec<_>>()
+            .join(";"),
+    );
 
     let methods: Vec<_> = input
         .items
@@ -147,6 +421,39 @@ pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
         .filter_map(|item| {
             if let TraitItem::Fn(TraitItemFn { attrs, sig, .. }) = item {
                 let mut method_sig = sig.clone();
+                // Polled or invoked across the boundary, which requires the async method.
+                let async_only = method_sig.inputs.iter().any(
+                    |x| matches!(x, FnArg::Typed(x) if is_streaming(&x.ty) || is_callback(&x.ty)),
+                );
+                let (attachments, attach): (Vec<Ident>, Vec<_>) = method_sig
+                    .inputs
+                    .iter()
+                    .filter_map(|x| match x {
+                        FnArg::Typed(x) if is_attachment(&x.ty) => match x.pat.as_ref() {
+                            Pat::Ident(ident) => {
+                                let ident = ident.ident.clone();
+                                let attach = if is_streaming(&x.ty) {
+                                    quote! {
+                                        ffi_rpc::request::Attachment::Stream(
+                                            ffi_rpc::stream::FfiStream::new(#ident, #codec),
+                                        )
+                                    }
+                                } else if is_callback(&x.ty) {
+                                    quote! {
+                                        ffi_rpc::request::Attachment::Callback(
+                                            ffi_rpc::callback::FfiCallback::new(#ident, #codec),
+                                        )
+                                    }
+                                } else {
+                                    quote!(ffi_rpc::request::Attachment::from(#ident))
+                                };
+                                Some((ident, attach))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .unzip();
                 let param: Vec<Ident> = method_sig
                     .inputs
                     .iter_mut()
@@ -164,7 +471,10 @@ pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
                                 panic!("unsupported argument type")
                             };
                             let ty = &x.ty;
-                            x.ty = if let Type::Path(x) = ty.as_ref() {
+                            x.ty = if is_attachment(ty) {
+                                // Moved or lent without the codec.
+                                ty.clone()
+                            } else if let Type::Path(x) = ty.as_ref() {
                                 if x.path.get_ident().is_some_and(|x| *x == "String") {
                                     parse_quote!(&str)
                                 } else {
@@ -187,17 +497,163 @@ pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
                 method_sig.inputs.insert(0, parse_quote!(&self));
                 let method_name = &sig.ident;
                 let api_name = format!("{}::{}", trait_name, method_name);
+                let ret_ty: Type = match &sig.output {
+                    ReturnType::Default => parse_quote!(()),
+                    ReturnType::Type(_, ty) => ty.as_ref().clone(),
+                };
+                if sig.asyncness.is_none()
+                    && (is_streaming(ok_type(&ret_ty)) || async_only)
+                {
+                    panic!("`Streaming` and `Callback` are not supported by the sync method `{}`", method_name);
+                }
+                // `Bytes` and `Streaming` are replied without the codec and delay the drain of `Registry::reload`.
+                // The call is done after the reply, except `Streaming` which cancels it when dropped.
+                let (invoke, decode_ok, disarm) = if is_bytes(ok_type(&ret_ty)) {
+                    (
+                        quote!(invoke),
+                        quote!(Ok::<_, String>(ffi_rpc::bytes::Bytes::from_library(ret, api))),
+                        quote!(guard.disarm();),
+                    )
+                } else if is_streaming(ok_type(&ret_ty)) {
+                    (
+                        quote!(invoke_stream),
+                        quote!(Ok::<_, String>(ffi_rpc::stream::Streaming::from_library(ret, #codec, api, guard))),
+                        quote!(),
+                    )
+                } else {
+                    (quote!(invoke), quote!(#codec.decode(&ret)), quote!(guard.disarm();))
+                };
+                // The `Err` branch of `Result` is replied as `ErrorKind::Failed` with payload.
+                let decode = if is_result(&ret_ty) {
+                    quote! {
+                        match ret {
+                            abi_stable::std_types::ROk(ret) => #decode_ok.map(Ok),
+                            abi_stable::std_types::RErr(ffi_rpc::error::RpcError {
+                                kind: ffi_rpc::error::ErrorKind::Failed,
+                                payload: abi_stable::std_types::RSome(payload),
+                                ..
+                            }) => #codec.decode(&payload).map(Err),
+                            abi_stable::std_types::RErr(e) => return Err(e.into()),
+                        }
+                        .map_err(ffi_rpc::Error::Deserialize)
+                    }
+                } else {
+                    quote! {
+                        let ret = ret.into_result()?;
+                        #decode_ok.map_err(ffi_rpc::Error::Deserialize)
+                    }
+                };
+                // The attachments are replaced by their indexes.
+                let value: Vec<_> = param
+                    .iter()
+                    .map(|x| match attachments.iter().position(|a| a == x) {
+                        Some(i) => {
+                            let i = i as u32;
+                            quote!(#i)
+                        }
+                        None => quote!(#x),
+                    })
+                    .collect();
+                // The arguments are always encoded as an array, e.g, `[]`, `[a]` and `[a, b]`.
+                let args = if param.is_empty() {
+                    quote!([(); 0])
+                } else {
+                    quote!((#(#value,)*))
+                };
+                let encode = if named {
+                    let generics: Vec<_> = (0..param.len())
+                        .map(|i| format_ident!("__T{}", i))
+                        .collect();
+                    quote! {
+                        #[derive(ffi_rpc::serde::Serialize)]
+                        #[serde(crate = "ffi_rpc::serde")]
+                        struct Params<#(#generics),*> {
+                            #(#param: #generics),*
+                        }
+                        let param = #codec.encode_named(&Params { #(#param: #value),* })
+                            .map_err(ffi_rpc::Error::Serialize)?;
+                    }
+                } else {
+                    quote! {
+                        let param = #args;
+                        let param = #codec.encode(&param)
+                            .map_err(ffi_rpc::Error::Serialize)?;
+                    }
+                };
+                let mut try_sig = method_sig.clone();
+                try_sig.ident = format_ident!("try_{}", method_name);
+                try_sig.output = parse_quote!(-> Result<#ret_ty, ffi_rpc::Error>);
+                let try_name = &try_sig.ident;
+                let context = quote! {
+                    // Delay the drain of `Registry::reload` until the reply is dropped.
+                    let api = self._ffi_ref.api();
+                    // Forward the context of the current call, if any.
+                    let mut context = ffi_rpc::context::CallContext::current().child();
+                    if let Some(timeout) = self._ffi_timeout {
+                        context.set_timeout(timeout);
+                    }
+                };
+                // The sync methods reply directly, the timeout is only passed as the deadline.
+                if sig.asyncness.is_none() {
+                    return Some(quote! {
+                        #(#attrs)*
+                        #vis #try_sig {
+                            const FUNC_NAME: &str = concat!(module_path!(), "::", #api_name);
+                            #encode
+                            #context
+                            let ret = api.invoke_sync()(
+                                _ffi_reg,
+                                ffi_rpc::request::Request::new(
+                                    abi_stable::std_types::RString::from(FUNC_NAME),
+                                    param.into(),
+                                    vec![#(#attach),*],
+                                ).with_context(context),
+                            );
+                            #decode
+                        }
+
+                        #(#attrs)*
+                        #vis #method_sig {
+                            match self.#try_name(_ffi_reg, #(#param),*) {
+                                Ok(ret) => ret,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    });
+                }
                 Some(quote! {
                     #(#attrs)*
-                    #vis #method_sig {
-                        let param = (#(#param),*);
+                    #vis #try_sig {
                         const FUNC_NAME: &str = concat!(module_path!(), "::", #api_name);
-                        let ret = self._ffi_ref.call()(
-                            abi_stable::std_types::RString::from(FUNC_NAME),
+                        #encode
+                        #context
+                        // Cancel the call if this future is dropped before the reply.
+                        let guard = context.cancel.clone().drop_guard();
+                        let call = ffi_rpc::error::catch_unwind(api.#invoke()(
                             _ffi_reg,
-                            rmp_serde::to_vec(&param).unwrap().into(),
-                        ).await;
-                        rmp_serde::from_slice(&ret).unwrap()
+                            ffi_rpc::request::Request::new(
+                                abi_stable::std_types::RString::from(FUNC_NAME),
+                                param.into(),
+                                vec![#(#attach),*],
+                            ).with_context(context),
+                        ));
+                        let ret = match self._ffi_timeout {
+                            Some(timeout) => ffi_rpc::timer::timeout(_ffi_reg, timeout, call)
+                                .await
+                                .ok_or_else(|| ffi_rpc::Error::Timeout(FUNC_NAME.to_string()))?,
+                            None => call.await,
+                        }
+                        .map_err(ffi_rpc::Error::Panic)?;
+                        #disarm
+                        #decode
+                    }
+
+                    #(#attrs)*
+                    #vis #method_sig {
+                        match self.#try_name(_ffi_reg, #(#param),*).await {
+                            Ok(ret) => ret,
+                            Err(e) => panic!("{}", e),
+                        }
                     }
                 })
             } else {
@@ -210,9 +666,19 @@ pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
         #[async_trait::async_trait]
         #input
 
+        #vis const #interface: ffi_rpc::plugin::Interface = ffi_rpc::plugin::Interface {
+            path: concat!(module_path!(), #interface_path),
+            fingerprint: #fingerprint,
+            codec: #enabled_codec,
+            named: #named,
+            methods: &[#(#method_info),*],
+        };
+
         impl #struct_name {
             #(#methods)*
         }
+
+        #(#params)*
     };
 
     expanded.into()
@@ -229,8 +695,8 @@ pub fn plugin_api_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// #[plugin_impl_trait]
 /// impl aa::bb::ServerApi for Server {}
 ///
-/// let mut r = Registry::default();
-/// Server::register_mock(&mut r, "server");    // register the mock plugin.
+/// let r = Registry::default();
+/// Server::register_mock(&r, "server").unwrap();    // register the mock plugin.
 /// ```
 #[proc_macro_attribute]
 pub fn plugin_impl_mock(_: TokenStream, item: TokenStream) -> TokenStream {
@@ -242,12 +708,18 @@ pub fn plugin_impl_mock(_: TokenStream, item: TokenStream) -> TokenStream {
 
         impl #ident {
             abi_stable::staticref!(const _FFI_API: ffi_rpc::plugin::PluginApiRef = ffi_rpc::plugin::PluginApiRef(unsafe {
-                abi_stable::prefix_type::WithMetadata::new(ffi_rpc::plugin::PluginApi { call: _ffi_call })
-                    .as_prefix()
+                abi_stable::prefix_type::WithMetadata::new(_FFI_PLUGIN_API).as_prefix()
             }));
 
-            #vis fn register_mock<S: Into<String>>(reg: &mut ffi_rpc::registry::Registry, id: S)  {
-                reg.item.insert(id.into().into(), *#ident::_FFI_API);
+            #vis fn register_mock<S: Into<String>>(reg: &ffi_rpc::registry::Registry, id: S) -> Result<(), ffi_rpc::Error> {
+                #ident::register_mock_with_config(reg, id, &())
+            }
+
+            #vis fn register_mock_with_config<S: Into<String>, C: ffi_rpc::serde::Serialize + ?Sized>(
+                reg: &ffi_rpc::registry::Registry,
+                id: S,
+                config: &C) -> Result<(), ffi_rpc::Error> {
+                reg.register(id, ffi_rpc::plugin::Plugin::from(*#ident::_FFI_API), config)
             }
         }
     };
@@ -258,12 +730,18 @@ pub fn plugin_impl_mock(_: TokenStream, item: TokenStream) -> TokenStream {
 ///
 /// The instance is named `"{struct_name.to_uppercase()}_INSTANCE"`.
 ///
-/// Note that if you need to init the instance at runtime,
-/// please create the static instance manually (e.g, using `OnceLock`).
+/// If the closure takes one argument, the instance is created with the deserialized config
+/// when the plugin is loaded (see `Client::new_with_config`), which MUST be in the same file as `plugin_impl_call`.
 /// ```ignore
 /// // static SERVER_INSTANCE: std::sync::LazyLock<Server> = std::sync::LazyLock::new(||Server{});
 /// #[plugin_impl_instance(|| Server{})] // pass the init closure.
 /// struct Server;
+///
+/// // static CLIENT_INSTANCE: ffi_rpc::plugin::Instance<Client> = ffi_rpc::plugin::Instance::new();
+/// #[plugin_impl_instance(|config: Config| Client{ config })]  // the type of config is required.
+/// struct Client {
+///     config: Config,
+/// }
 /// ```
 #[proc_macro_attribute]
 pub fn plugin_impl_instance(attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -273,15 +751,27 @@ pub fn plugin_impl_instance(attr: TokenStream, item: TokenStream) -> TokenStream
     let vis = &input.vis;
     let instance = format_ident!("{}_INSTANCE", input.ident.to_string().to_uppercase());
 
-    let expanded = quote! {
-        #vis static #instance: std::sync::LazyLock<#ident> = std::sync::LazyLock::new(#init);
+    let expanded = match init.inputs.len() {
+        0 => quote! {
+            #vis static #instance: std::sync::LazyLock<#ident> = std::sync::LazyLock::new(#init);
 
-        #input
+            #input
+        },
+        1 => quote! {
+            #vis static #instance: ffi_rpc::plugin::Instance<#ident> = ffi_rpc::plugin::Instance::new();
+
+            pub fn _ffi_init(config: &[u8]) -> Result<(), ffi_rpc::error::RpcError> {
+                #instance.init(config, #init)
+            }
+
+            #input
+        },
+        _ => panic!("expected `||` or `|config: Config|` closure"),
     };
     expanded.into()
 }
 
-/// Define the root module in the plugin, `_ffi_call` must be defined in the same file.
+/// Define the root module in the plugin, `_FFI_PLUGIN_API` must be defined in the same file.
 ///
 /// Note that each plugin MUST have ONLY one root module.
 /// You might need to customize `_ffi_call` if multiple instances in one plugin is needed (not common).
@@ -297,12 +787,166 @@ pub fn plugin_impl_root(_: TokenStream, item: TokenStream) -> TokenStream {
 
         #[abi_stable::export_root_module]
         pub fn _ffi_root_module() -> ffi_rpc::plugin::PluginApiRef {
-            ffi_rpc::plugin::PluginApi { call: _ffi_call }.leak_into_prefix()
+            _FFI_PLUGIN_API.leak_into_prefix()
+        }
+    };
+    expanded.into()
+}
+
+/// Define the hook called when the plugin is loaded, it MUST be in the same file as `plugin_impl_call`.
+///
+/// The hook takes the registry and optionally the config passed by the host.
+/// The config is deserialized, it is `()` when loading with `Client::new`.
+/// Return `Result<(), E: ToString>` to report the failure, which stops loading the plugin.
+/// The registry is not locked during the hook, other plugins can be registered in it.
+/// ```ignore
+/// #[plugin_impl_on_load]
+/// fn init(reg: &Registry, config: Option<String>) -> Result<(), String> {
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn plugin_impl_on_load(_: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
+    let ident = &input.sig.ident;
+    let call = match input.sig.inputs.len() {
+        1 => quote!(#ident(reg)),
+        2 => quote! {
+            #ident(reg, match ffi_rpc::codec::Codec::MessagePack.decode(&config) {
+                Ok(v) => v,
+                Err(e) => return abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                    ffi_rpc::error::ErrorKind::InvalidParams,
+                    e.to_string(),
+                )),
+            })
+        },
+        _ => panic!("expected `(reg)` or `(reg, config)` arguments"),
+    };
+    let ret = hook_reply(&input.sig.output, call);
+    let expanded = quote! {
+        #input
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_on_load(reg: &ffi_rpc::registry::Registry,
+            config: abi_stable::std_types::RVec<u8>) -> abi_stable::std_types::RResult<(), ffi_rpc::error::RpcError> {
+            #ret
+        }
+    };
+    expanded.into()
+}
+
+/// Declare the plugins or interfaces required by the plugin, it MUST be in the same file as `plugin_impl_call`.
+///
+/// String literals are the plugin ids in the registry, paths are the interfaces implemented by any plugin.
+/// The plugin fails to register with `Error::MissingDependency` if any of them is not registered,
+/// and the loader registers the plugins in the topological order.
+/// ```ignore
+/// #[plugin_impl_requires("server", aa::bb::ClientApi)]
+/// struct Api;
+/// ```
+#[proc_macro_attribute]
+pub fn plugin_impl_requires(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = parse_macro_input!(attr as DependencyList);
+    let input = parse_macro_input!(item as ItemStruct);
+    let deps: Vec<_> = attr
+        .deps
+        .iter()
+        .map(|x| match x {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(id), ..
+            }) => quote!(ffi_rpc::plugin::Dependency::Plugin(#id.into())),
+            Expr::Path(path) => {
+                let interface = interface_const(&path.path);
+                quote!(ffi_rpc::plugin::Dependency::from(&#interface))
+            }
+            _ => panic!("expected plugin id or interface path"),
+        })
+        .collect();
+    let expanded = quote! {
+        #input
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_dependencies() -> abi_stable::std_types::RVec<ffi_rpc::plugin::Dependency> {
+            abi_stable::std_types::RVec::from(vec![#(#deps),*])
+        }
+    };
+    expanded.into()
+}
+
+struct DependencyList {
+    deps: Punctuated<Expr, Token![,]>,
+}
+
+impl Parse for DependencyList {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let deps = Punctuated::parse_terminated(input)?;
+        Ok(DependencyList { deps })
+    }
+}
+
+/// Define the hook called when the registry shuts down, it MUST be in the same file as `plugin_impl_call`.
+///
+/// Return `Result<(), E: ToString>` to report the failure.
+/// ```ignore
+/// #[plugin_impl_on_unload]
+/// fn stop() {}
+/// ```
+#[proc_macro_attribute]
+pub fn plugin_impl_on_unload(_: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
+    let ident = &input.sig.ident;
+    if !input.sig.inputs.is_empty() {
+        panic!("expected no arguments");
+    }
+    let ret = hook_reply(&input.sig.output, quote!(#ident()));
+    let expanded = quote! {
+        #input
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_on_unload() -> abi_stable::std_types::RResult<(), ffi_rpc::error::RpcError> {
+            #ret
         }
     };
     expanded.into()
 }
 
+/// The paths and `name = value` options, seperated by a comma.
+struct Args {
+    paths: Vec<Path>,
+    options: Vec<MetaNameValue>,
+}
+
+impl Args {
+    fn option(&self, name: &str) -> Option<&Expr> {
+        self.options
+            .iter()
+            .find(|x| x.path.is_ident(name))
+            .map(|x| &x.value)
+    }
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Args {
+            paths: Vec::new(),
+            options: Vec::new(),
+        };
+        for meta in Punctuated::<Meta, Token![,]>::parse_terminated(input)? {
+            match meta {
+                Meta::Path(path) => args.paths.push(path),
+                Meta::NameValue(option) => args.options.push(option),
+                Meta::List(list) => {
+                    return Err(syn::Error::new_spanned(
+                        list,
+                        "expected path or `name = value`",
+                    ))
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
 struct TraitList {
     traits: Punctuated<Path, Token![,]>,
 }
@@ -314,10 +958,15 @@ impl Parse for TraitList {
     }
 }
 
-/// Define the `_ffi_call` function.
+/// Define the `_ffi_invoke`, `_ffi_invoke_stream`, `_ffi_invoke_sync`, `_ffi_call`, `_ffi_fingerprint` and `_ffi_interfaces` functions,
+/// which are collected in the `_FFI_PLUGIN_API` constant with the lifecycle hooks.
+/// The instance created with the config is initialized before the `on_load` hook.
 /// All implemented traits should be passed using full path, seperated by a comma.
 ///
-/// Note that each plugin MUST have ONLY one `_ffi_call` function.
+/// Panics in the implementations are caught in each poll and replied as `ErrorKind::Panic`.
+/// `CallContext::current` returns the context of the call while polling the implementations.
+///
+/// Note that each plugin MUST have ONLY one `_ffi_invoke` function.
 /// You might need to customize it if multiple instances in one plugin is needed (not common).
 /// ```ignore
 /// #[plugin_impl_call(aa::bb::ClientApi1, aa::bb::ClientApi2)]
@@ -334,58 +983,164 @@ pub fn plugin_impl_call(attr: TokenStream, item: TokenStream) -> TokenStream {
     let attr = parse_macro_input!(attr as TraitList);
     let input = parse_macro_input!(item as ItemStruct);
     let ident = &input.ident;
-    let cases: Vec<_> = attr
-        .traits
-        .into_iter()
-        .map(|x| {
-            let str = x
-                .segments
-                .iter()
-                .map(|x| x.ident.to_string())
-                .collect::<Vec<_>>()
-                .join("::");
-            let prefix = format!("{}::", str);
-            let func = format_ident!("parse_{}", str.replace("::", "_").to_lowercase());
-            quote! {
-                if (func.as_str().starts_with(#prefix)){
-                    return #ident::#func(func, reg, param).await;
+    let interfaces: Vec<_> = attr.traits.iter().map(interface_const).collect();
+    // Route to `parse_*`, `parse_stream_*` or `parse_sync_*` of each trait.
+    let cases = |prefix: &str, sync: bool| -> Vec<_> {
+        let call = if sync { quote!() } else { quote!(.await) };
+        attr.traits
+            .iter()
+            .map(|x| {
+                let str = x
+                    .segments
+                    .iter()
+                    .map(|x| x.ident.to_string())
+                    .collect::<Vec<_>>()
+                    .join("::");
+                let name = format!("{}::", str);
+                let func = format_ident!("{}_{}", prefix, str.replace("::", "_").to_lowercase());
+                quote! {
+                    if (req.func.as_str().starts_with(#name)){
+                        return #ident::#func(reg, req)#call;
+                    }
                 }
+            })
+            .collect()
+    };
+    let unknown = quote! {
+        abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+            ffi_rpc::error::ErrorKind::UnknownFunction,
+            req.func,
+        ))
+    };
+    let dispatch = |prefix: &str| {
+        let cases = cases(prefix, false);
+        if cfg!(feature = "tokio") {
+            quote! {
+                // The context is current while polling the implementation.
+                let context = req.context.clone();
+                async_ffi::BorrowingFfiFuture::new(ffi_rpc::error::catch_panic(context.scope(async move {
+                    let block_func = async move {
+                        #(#cases)*
+                        #unknown
+                    };
+                    if tokio::runtime::Handle::try_current().is_ok() {
+                        block_func.await
+                    } else {
+                        _FFI_RUNTIME.block_on(block_func)
+                    }
+                })))
             }
-        })
-        .collect();
-    let call = if cfg!(feature = "tokio") {
-        quote! {
-            static _RUNTIME: std::sync::LazyLock<tokio::runtime::Runtime> =
-                std::sync::LazyLock::new(|| tokio::runtime::Runtime::new().unwrap());
-            async_ffi::BorrowingFfiFuture::new(async move {
-                let block_func = async {
+        } else {
+            quote! {
+                // The context is current while polling the implementation.
+                let context = req.context.clone();
+                async_ffi::BorrowingFfiFuture::new(ffi_rpc::error::catch_panic(context.scope(async move {
                     #(#cases)*
-                    panic!("{}", format!("Function `{func}` is not defined in the library"));
-                };
-                if tokio::runtime::Handle::try_current().is_ok() {
-                    block_func.await
-                } else {
-                    _RUNTIME.block_on(block_func)
-                }
-            })
+                    #unknown
+                })))
+            }
         }
-    } else {
+    };
+    let call = dispatch("parse");
+    let call_stream = dispatch("parse_stream");
+    let sync_cases = cases("parse_sync", true);
+    // Enter the runtime so the implementation can spawn tasks.
+    let enter = cfg!(feature = "tokio").then(|| {
         quote! {
-            async_ffi::BorrowingFfiFuture::new(async move {
-                #(#cases)*
-                panic!("{}", format!("Function `{func}` is not defined in the library"));
-            })
+            let _runtime = tokio::runtime::Handle::try_current()
+                .is_err()
+                .then(|| _FFI_RUNTIME.enter());
+        }
+    });
+    let call_sync = quote! {
+        // The context is current while running the implementation.
+        let context = req.context.clone();
+        #enter
+        let block_func = move || {
+            #(#sync_cases)*
+            #unknown
+        };
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| context.enter(block_func))) {
+            Ok(ret) => ret,
+            Err(e) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                ffi_rpc::error::ErrorKind::Panic,
+                ffi_rpc::error::panic_message(&*e),
+            )),
         }
     };
+    let runtime = cfg!(feature = "tokio").then(|| {
+        quote! {
+            static _FFI_RUNTIME: std::sync::LazyLock<tokio::runtime::Runtime> =
+                std::sync::LazyLock::new(|| tokio::runtime::Runtime::new().unwrap());
+        }
+    });
     let expanded = quote! {
         #input
 
+        #runtime
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_invoke<'fut>(reg: &'fut ffi_rpc::registry::Registry,
+            req: ffi_rpc::request::Request<'fut>) -> async_ffi::BorrowingFfiFuture<'fut, ffi_rpc::error::Reply> {
+            #call
+        }
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_invoke_stream<'fut>(reg: &'fut ffi_rpc::registry::Registry,
+            req: ffi_rpc::request::Request<'fut>) -> async_ffi::BorrowingFfiFuture<'fut, ffi_rpc::stream::StreamReply> {
+            #call_stream
+        }
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_invoke_sync<'a>(reg: &'a ffi_rpc::registry::Registry,
+            req: ffi_rpc::request::Request<'a>) -> ffi_rpc::error::Reply {
+            #call_sync
+        }
+
         #[abi_stable::sabi_extern_fn]
         pub fn _ffi_call<'fut>(func: abi_stable::std_types::RString,
             reg: &'fut ffi_rpc::registry::Registry,
-            param: abi_stable::std_types::RVec<u8>) -> async_ffi::BorrowingFfiFuture<'fut, abi_stable::std_types::RVec<u8>> {
-            #call
+            param: abi_stable::std_types::RVec<u8>) -> async_ffi::BorrowingFfiFuture<'fut, ffi_rpc::error::Reply> {
+            _ffi_invoke(reg, ffi_rpc::request::Request::new(func, param, Vec::new()))
         }
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_fingerprint(path: abi_stable::std_types::RStr<'_>) -> abi_stable::std_types::ROption<u64> {
+            [#(#interfaces),*]
+                .into_iter()
+                .find(|x| x.path == path.as_str())
+                .map(|x| x.fingerprint)
+                .into()
+        }
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_interfaces() -> abi_stable::std_types::RVec<ffi_rpc::plugin::InterfaceInfo> {
+            [#(#interfaces),*].iter().map(Into::into).collect()
+        }
+
+        #[allow(unused_imports)]
+        use ffi_rpc::plugin::hooks::*;
+
+        #[abi_stable::sabi_extern_fn]
+        pub fn _ffi_load(reg: &ffi_rpc::registry::Registry,
+            config: abi_stable::std_types::RVec<u8>) -> abi_stable::std_types::RResult<(), ffi_rpc::error::RpcError> {
+            if let Err(e) = _ffi_init(&config) {
+                return abi_stable::std_types::RErr(e);
+            }
+            _ffi_on_load(reg, config)
+        }
+
+        pub const _FFI_PLUGIN_API: ffi_rpc::plugin::PluginApi = ffi_rpc::plugin::PluginApi {
+            call: _ffi_call,
+            fingerprint: _ffi_fingerprint,
+            interfaces: _ffi_interfaces,
+            on_load: _ffi_load,
+            on_unload: _ffi_on_unload,
+            dependencies: _ffi_dependencies,
+            invoke: _ffi_invoke,
+            invoke_stream: _ffi_invoke_stream,
+            invoke_sync: _ffi_invoke_sync,
+        };
     };
     expanded.into()
 }
@@ -425,6 +1180,9 @@ pub fn plugin_impl_call(attr: TokenStream, item: TokenStream) -> TokenStream {
 ///     }
 /// }
 /// ```
+///
+/// The sync methods in the interface are implemented as `fn` without `async`,
+/// and invoked by `parse_sync_*` directly.
 #[proc_macro_attribute]
 pub fn plugin_impl_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemImpl);
@@ -451,7 +1209,8 @@ pub fn plugin_impl_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
         .map(|x| {
             if let ImplItem::Fn(item) = x {
                 let ident = &item.sig.ident;
-                let param: Vec<Ident> = item
+                let sync = item.sig.asyncness.is_none();
+                let (param, ty): (Vec<Ident>, Vec<&Type>) = item
                     .sig
                     .inputs
                     .iter()
@@ -459,43 +1218,434 @@ pub fn plugin_impl_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
                     .map(|x| match x {
                         FnArg::Typed(x) => {
                             if let Pat::Ident(ident) = x.pat.as_ref() {
-                                ident.ident.clone()
+                                (ident.ident.clone(), x.ty.as_ref())
                             } else {
                                 panic!("unknown argument name")
                             }
                         }
                         _ => panic!("unsupported argument"),
                     })
+                    .unzip();
+                // The attachments are decoded as their indexes.
+                let wire: Vec<_> = ty
+                    .iter()
+                    .map(|x| {
+                        if is_attachment(x) {
+                            quote!(u32)
+                        } else {
+                            quote!(_)
+                        }
+                    })
                     .collect();
+                let decode_args = if param.is_empty() {
+                    quote!(CODEC.decode::<[(); 0]>(&req.param).map(|[]| ()))
+                } else {
+                    quote!(CODEC.decode::<(#(#wire,)*)>(&req.param))
+                };
+                // Take the owned attachments before lending the borrowed ones.
+                let (owned, take_owned): (Vec<_>, Vec<_>) = param
+                    .iter()
+                    .zip(&ty)
+                    .filter_map(|(x, ty)| {
+                        if is_bytes(ty) {
+                            Some((x, quote!(req.take_bytes(#x))))
+                        } else if is_streaming(ty) {
+                            Some((x, quote!(req.take_stream(#x, CODEC))))
+                        } else if is_callback(ty) {
+                            Some((x, quote!(req.take_callback(#x, CODEC))))
+                        } else {
+                            None
+                        }
+                    })
+                    .unzip();
+                let borrowed: Vec<_> = param
+                    .iter()
+                    .zip(&ty)
+                    .filter(|(_, x)| is_byte_slice(x))
+                    .map(|(x, _)| x)
+                    .collect();
+                let take = (!owned.is_empty()).then(|| quote!(let mut req = req;));
+                let mut params = trait_path.clone();
+                let last = params.segments.last_mut().unwrap();
+                last.ident = params_struct(&last.ident, ident);
                 let api_name = format!("{}::{}", trait_str, ident);
-                quote! {
+                let stream = matches!(&item.sig.output, ReturnType::Type(_, ty) if is_streaming(ok_type(ty)));
+                if sync && (stream || ty.iter().any(|x| is_streaming(x) || is_callback(x))) {
+                    panic!("`Streaming` and `Callback` are not supported by the sync method `{}`", ident);
+                }
+                let call = if sync { quote!() } else { quote!(.await) };
+                let encode = match &item.sig.output {
+                    ReturnType::Type(_, ty) if is_bytes(ok_type(ty)) => {
+                        quote!(abi_stable::std_types::ROk(ret.into()))
+                    }
+                    _ if stream => {
+                        quote!(abi_stable::std_types::ROk(ffi_rpc::stream::FfiStream::new(ret, CODEC)))
+                    }
+                    _ => quote! {
+                        match CODEC.encode(&ret) {
+                            Ok(v) => abi_stable::std_types::ROk(v.into()),
+                            Err(e) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                                ffi_rpc::error::ErrorKind::Failed,
+                                e,
+                            )),
+                        }
+                    },
+                };
+                let reply = match &item.sig.output {
+                    ReturnType::Type(_, ty) if is_result(ty) => quote! {
+                        match ret {
+                            Ok(ret) => #encode,
+                            Err(e) => match CODEC.encode(&e) {
+                                Ok(v) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                                    ffi_rpc::error::ErrorKind::Failed,
+                                    concat!("`", #api_name, "` returned an error"),
+                                ).with_payload(v)),
+                                Err(e) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                                    ffi_rpc::error::ErrorKind::Failed,
+                                    e,
+                                )),
+                            },
+                        }
+                    },
+                    _ => encode,
+                };
+                let case = quote! {
                     #api_name => {
-                        let (#(#param),*) = rmp_serde::from_slice(&param).unwrap();
-                        rmp_serde::to_vec(&#trait_path::#ident(#instance, reg, #(#param),*).await)
-                            .unwrap()
-                            .into()
+                        #take
+                        let decoded = if NAMED {
+                            CODEC.decode(&req.param).map(#params::into_tuple)
+                        } else {
+                            #decode_args
+                        };
+                        let (#(#param,)*) = match decoded {
+                            Ok(v) => v,
+                            Err(e) => return abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                                ffi_rpc::error::ErrorKind::InvalidParams,
+                                e,
+                            )),
+                        };
+                        #(
+                            let #owned = match #take_owned {
+                                Ok(v) => v,
+                                Err(e) => return abi_stable::std_types::RErr(e),
+                            };
+                        )*
+                        #(
+                            let #borrowed = match req.bytes(#borrowed) {
+                                Ok(v) => v,
+                                Err(e) => return abi_stable::std_types::RErr(e),
+                            };
+                        )*
+                        let ret = #trait_path::#ident(#instance, reg, #(#param),*)#call;
+                        #reply
                     }
-                }
+                };
+                (stream, sync, case)
             } else {
                 panic!("unsupported implement function");
             }
         })
         .collect();
+    let stream_cases = cases.iter().filter(|x| x.0).map(|x| &x.2);
+    let sync_cases = cases.iter().filter(|x| x.1).map(|x| &x.2);
+    let cases = cases.iter().filter(|x| !x.0 && !x.1).map(|x| &x.2);
     let func = format_ident!("parse_{}", trait_str.replace("::", "_").to_lowercase());
+    let stream_func = format_ident!(
+        "parse_stream_{}",
+        trait_str.replace("::", "_").to_lowercase()
+    );
+    let sync_func = format_ident!("parse_sync_{}", trait_str.replace("::", "_").to_lowercase());
+    let interface = interface_const(trait_path);
     let expanded = quote! {
         #[async_trait::async_trait]
         #input
 
         impl #ty {
-            pub async fn #func(func: abi_stable::std_types::RString,
-                reg: &ffi_rpc::registry::Registry,
-                param: abi_stable::std_types::RVec<u8>) -> abi_stable::std_types::RVec<u8> {
-                match func.as_str() {
+            pub async fn #func(reg: &ffi_rpc::registry::Registry,
+                req: ffi_rpc::request::Request<'_>) -> ffi_rpc::error::Reply {
+                // Use the codec declared in the interface.
+                const CODEC: ffi_rpc::codec::Codec = #interface.codec;
+                const NAMED: bool = #interface.named;
+                match req.func.as_str() {
                     #(#cases)*
-                    _ => panic!("Function is not defined in the library"),
+                    _ => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                        ffi_rpc::error::ErrorKind::UnknownFunction,
+                        req.func,
+                    )),
+                }
+            }
+
+            /// Invoke the methods returning `Streaming`.
+            pub async fn #stream_func(reg: &ffi_rpc::registry::Registry,
+                req: ffi_rpc::request::Request<'_>) -> ffi_rpc::stream::StreamReply {
+                const CODEC: ffi_rpc::codec::Codec = #interface.codec;
+                const NAMED: bool = #interface.named;
+                match req.func.as_str() {
+                    #(#stream_cases)*
+                    _ => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                        ffi_rpc::error::ErrorKind::UnknownFunction,
+                        req.func,
+                    )),
+                }
+            }
+
+            /// Invoke the sync methods.
+            pub fn #sync_func(reg: &ffi_rpc::registry::Registry,
+                req: ffi_rpc::request::Request<'_>) -> ffi_rpc::error::Reply {
+                const CODEC: ffi_rpc::codec::Codec = #interface.codec;
+                const NAMED: bool = #interface.named;
+                match req.func.as_str() {
+                    #(#sync_cases)*
+                    _ => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                        ffi_rpc::error::ErrorKind::UnknownFunction,
+                        req.func,
+                    )),
                 }
             }
         }
     };
     expanded.into()
 }
+
+/// Remove `#[ffi_rpc(default)]` from the arguments, and return whether each argument has it.
+///
+/// The defaults only work with the named arguments, since the positional ones can not be omitted.
+fn strip_defaults(sig: &mut Signature, named: bool) -> Vec<bool> {
+    let default: Vec<_> = sig
+        .inputs
+        .iter_mut()
+        .map(|x| {
+            let FnArg::Typed(x) = x else {
+                return false;
+            };
+            let len = x.attrs.len();
+            x.attrs.retain(|attr| {
+                if !attr.path().is_ident("ffi_rpc") {
+                    return true;
+                }
+                match attr.parse_args::<Ident>() {
+                    Ok(x) if x == "default" => false,
+                    _ => panic!("expected `#[ffi_rpc(default)]`"),
+                }
+            });
+            match len - x.attrs.len() {
+                0 => false,
+                1 => true,
+                _ => panic!("duplicate `#[ffi_rpc(default)]`"),
+            }
+        })
+        .collect();
+    if !named && default.contains(&true) {
+        panic!("`#[ffi_rpc(default)]` requires `named = true`");
+    }
+    default
+}
+
+/// Get the name of the struct to decode the named arguments, e.g, `_ClientApi_add_Params`.
+fn params_struct(trait_name: &Ident, method: &Ident) -> Ident {
+    format_ident!("_{}_{}_Params", trait_name, method)
+}
+
+/// Whether the type is `Result<T, E>`.
+fn is_result(ty: &Type) -> bool {
+    let Type::Path(ty) = ty else {
+        return false;
+    };
+    let Some(last) = ty.path.segments.last() else {
+        return false;
+    };
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return false;
+    };
+    last.ident == "Result" && args.args.len() == 2
+}
+
+/// Whether the type is `Bytes`, which is moved without the codec.
+fn is_bytes(ty: &Type) -> bool {
+    let Type::Path(ty) = ty else {
+        return false;
+    };
+    ty.path
+        .segments
+        .last()
+        .is_some_and(|x| x.ident == "Bytes" && x.arguments.is_none())
+}
+
+/// Whether the type is `&[u8]`, which is lent without the codec.
+fn is_byte_slice(ty: &Type) -> bool {
+    let Type::Reference(ty) = ty else {
+        return false;
+    };
+    let Type::Slice(slice) = ty.elem.as_ref() else {
+        return false;
+    };
+    ty.mutability.is_none() && matches!(slice.elem.as_ref(), Type::Path(x) if x.path.is_ident("u8"))
+}
+
+/// Whether the type is `Streaming<T>`, which is polled across the boundary.
+fn is_streaming(ty: &Type) -> bool {
+    is_generic(ty, "Streaming")
+}
+
+/// Whether the type is `Callback<A, R>`, which is invoked across the boundary.
+fn is_callback(ty: &Type) -> bool {
+    is_generic(ty, "Callback")
+}
+
+/// Whether the type is `name<..>`.
+fn is_generic(ty: &Type, name: &str) -> bool {
+    let Type::Path(ty) = ty else {
+        return false;
+    };
+    ty.path.segments.last().is_some_and(|x| {
+        x.ident == name && matches!(&x.arguments, PathArguments::AngleBracketed(_))
+    })
+}
+
+/// Whether the argument is passed as an attachment of `Request`.
+fn is_attachment(ty: &Type) -> bool {
+    is_bytes(ty) || is_byte_slice(ty) || is_streaming(ty) || is_callback(ty)
+}
+
+/// Get the type encoded by the codec, the attachments are replaced by their indexes.
+fn wire_type(ty: &Type) -> Type {
+    if is_attachment(ty) {
+        parse_quote!(u32)
+    } else {
+        ty.clone()
+    }
+}
+
+/// Get `T` of `Result<T, E>`, or the type itself.
+fn ok_type(ty: &Type) -> &Type {
+    let Type::Path(path) = ty else {
+        return ty;
+    };
+    let Some(last) = path.path.segments.last() else {
+        return ty;
+    };
+    match &last.arguments {
+        PathArguments::AngleBracketed(args) if last.ident == "Result" && args.args.len() == 2 => {
+            match args.args.first() {
+                Some(GenericArgument::Type(ok)) => ok,
+                _ => ty,
+            }
+        }
+        _ => ty,
+    }
+}
+
+/// Get the path of the interface constant for the trait, e.g, `aa::Api` to `aa::API_INTERFACE`.
+fn interface_const(path: &Path) -> Path {
+    let mut path = path.clone();
+    let last = path.segments.last_mut().unwrap();
+    last.ident = format_ident!("{}_INTERFACE", last.ident.to_string().to_uppercase());
+    path
+}
+
+/// FNV-1a hash, which is stable across compilers.
+fn fingerprint(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ x as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Format the method signature, e.g, `async fn add(a: i32, b: i32) -> i32`.
+fn signature(sig: &Signature) -> String {
+    let tidy = |x: &dyn ToTokens| {
+        [
+            (" <", "<"),
+            ("< ", "<"),
+            (" >", ">"),
+            (" ::", "::"),
+            (":: ", "::"),
+            (" ,", ","),
+            ("& ", "&"),
+            (" ;", ";"),
+        ]
+        .iter()
+        .fold(x.to_token_stream().to_string(), |s, (from, to)| {
+            s.replace(from, to)
+        })
+    };
+    let param: Vec<_> = sig
+        .inputs
+        .iter()
+        .map(|x| match x {
+            FnArg::Typed(x) => format!("{}: {}", tidy(&x.pat), tidy(&x.ty)),
+            FnArg::Receiver(x) => tidy(x),
+        })
+        .collect();
+    let ret = match &sig.output {
+        ReturnType::Default => String::new(),
+        ReturnType::Type(_, ty) => format!(" -> {}", tidy(ty.as_ref())),
+    };
+    format!(
+        "{}fn {}({}){}",
+        if sig.asyncness.is_some() {
+            "async "
+        } else {
+            ""
+        },
+        sig.ident,
+        param.join(", "),
+        ret
+    )
+}
+
+/// Catch the panic of the hook and turn the result into `RResult<(), RpcError>`.
+fn hook_reply(output: &ReturnType, call: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let ret = match output {
+        ReturnType::Default => quote! {
+            #call;
+            abi_stable::std_types::ROk(())
+        },
+        ReturnType::Type(..) => quote! {
+            match #call {
+                Ok(()) => abi_stable::std_types::ROk(()),
+                Err(e) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                    ffi_rpc::error::ErrorKind::Failed,
+                    e.to_string(),
+                )),
+            }
+        },
+    };
+    quote! {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| { #ret })) {
+            Ok(ret) => ret,
+            Err(e) => abi_stable::std_types::RErr(ffi_rpc::error::RpcError::new(
+                ffi_rpc::error::ErrorKind::Panic,
+                ffi_rpc::error::panic_message(&*e),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_defaults_named() {
+        let mut sig: Signature = parse_quote!(fn f(a: i32, #[ffi_rpc(default)] b: i32));
+        assert_eq!(strip_defaults(&mut sig, true), [false, true]);
+        assert_eq!(
+            sig.to_token_stream().to_string(),
+            "fn f (a : i32 , b : i32)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "`#[ffi_rpc(default)]` requires `named = true`")]
+    fn strip_defaults_positional() {
+        let mut sig: Signature = parse_quote!(fn f(a: i32, #[ffi_rpc(default)] b: i32));
+        strip_defaults(&mut sig, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected `#[ffi_rpc(default)]`")]
+    fn strip_defaults_unknown() {
+        let mut sig: Signature = parse_quote!(fn f(#[ffi_rpc(skip)] a: i32));
+        strip_defaults(&mut sig, true);
+    }
+}